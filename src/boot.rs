@@ -0,0 +1,413 @@
+use core::prelude::*;
+use collections::{Vec, String};
+
+use protocol::DevicePath;
+use variable::{self, VariableAttributes, GLOBAL_VARIABLE, VARIABLE_NON_VOLATILE, VARIABLE_BOOTSERVICE_ACCESS, VARIABLE_RUNTIME_ACCESS};
+use Status;
+
+efi_flags!(LoadOptionAttributes: u32 {
+	LOAD_OPTION_ACTIVE = 0x00000001,
+	LOAD_OPTION_FORCE_RECONNECT = 0x00000002,
+	LOAD_OPTION_HIDDEN = 0x00000008,
+	LOAD_OPTION_CATEGORY = 0x00001F00, // a field rather than a flag, see LoadOption::category()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadOptionCategory {
+	Boot, // shown in the boot menu and part of normal boot
+	App, // only run on explicit request, e.g. diagnostics
+	Reserved(u32)
+}
+
+impl LoadOptionCategory {
+	fn bits(&self) -> u32 {
+		match *self {
+			LoadOptionCategory::Boot => 0x000,
+			LoadOptionCategory::App => 0x100,
+			LoadOptionCategory::Reserved(bits) => bits & LOAD_OPTION_CATEGORY.bits()
+		}
+	}
+}
+
+// what the boot manager variables are stored with
+fn attributes() -> VariableAttributes {
+	VARIABLE_NON_VOLATILE | VARIABLE_BOOTSERVICE_ACCESS | VARIABLE_RUNTIME_ACCESS
+}
+
+// which family of Xxxx####/XxxxOrder variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadOptionKind {
+	Boot,
+	Driver,
+	SysPrep
+}
+
+impl LoadOptionKind {
+	fn prefix(&self) -> &'static str {
+		match *self {
+			LoadOptionKind::Boot => "Boot",
+			LoadOptionKind::Driver => "Driver",
+			LoadOptionKind::SysPrep => "SysPrep"
+		}
+	}
+
+	fn variable(&self, number: u16) -> String {
+		format!("{}{:04X}", self.prefix(), number)
+	}
+
+	fn order_variable(&self) -> String {
+		format!("{}Order", self.prefix())
+	}
+}
+
+// an EFI_LOAD_OPTION, the contents of a Boot####, Driver#### or SysPrep#### variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadOption {
+	pub attributes: LoadOptionAttributes,
+	pub description: String,
+	pub file_path_list: Vec<u8>, // one or more device paths back to back, the first one is what gets loaded
+	pub optional_data: Vec<u8> // passed to the image as its load options
+}
+
+impl LoadOption {
+	pub fn parse(data: &[u8]) -> ::Result<LoadOption> {
+		if data.len() < 6 {
+			return Err(Status::VolumeCorrupted);
+		}
+		let attributes = read_u32(&data[0..4]);
+		let file_path_list_length = read_u16(&data[4..6]) as usize;
+
+		let mut description = Vec::new();
+		let mut cursor = 6;
+		loop {
+			if cursor + 2 > data.len() {
+				return Err(Status::VolumeCorrupted);
+			}
+			let c = read_u16(&data[cursor..cursor + 2]);
+			cursor += 2;
+			if c == 0 {
+				break;
+			}
+			description.push(c);
+		}
+
+		if cursor + file_path_list_length > data.len() {
+			return Err(Status::VolumeCorrupted);
+		}
+		let file_path_list = &data[cursor..cursor + file_path_list_length];
+		cursor += file_path_list_length;
+
+		Ok(LoadOption {
+			attributes: LoadOptionAttributes::from_bits(attributes),
+			description: String::from_utf16_lossy(&description),
+			file_path_list: file_path_list.to_vec(),
+			optional_data: data[cursor..].to_vec()
+		})
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut data = Vec::with_capacity(6 + self.description.len() * 2 + 2 + self.file_path_list.len() + self.optional_data.len());
+		write_u32(&mut data, self.attributes.bits());
+		write_u16(&mut data, self.file_path_list.len() as u16);
+		for c in ::to_utf16(&self.description) {
+			write_u16(&mut data, c);
+		}
+		data.extend(self.file_path_list.iter().cloned());
+		data.extend(self.optional_data.iter().cloned());
+		data
+	}
+
+	pub fn is_active(&self) -> bool {
+		self.attributes.contains(LOAD_OPTION_ACTIVE)
+	}
+
+	pub fn category(&self) -> LoadOptionCategory {
+		match self.attributes.bits() & LOAD_OPTION_CATEGORY.bits() {
+			0x000 => LoadOptionCategory::Boot,
+			0x100 => LoadOptionCategory::App,
+			bits => LoadOptionCategory::Reserved(bits)
+		}
+	}
+
+	pub fn set_category(&mut self, category: LoadOptionCategory) {
+		self.attributes = (self.attributes & !LOAD_OPTION_CATEGORY) | LoadOptionAttributes::from_bits(category.bits());
+	}
+
+	// the first device path, None unless every node of it up to the end node lies within
+	// file_path_list, the data comes straight from nvram and can be anything
+	pub fn file_path(&self) -> Option<&DevicePath> {
+		let list = &self.file_path_list;
+		let mut offset = 0;
+		loop {
+			// every node starts with a 4 byte header
+			if offset + 4 > list.len() {
+				return None;
+			}
+			let node = unsafe { &*(list[offset..].as_ptr() as *const DevicePath) };
+			if node.len() < 4 || offset + node.len() > list.len() {
+				return None;
+			}
+			if node.is_end() {
+				break;
+			}
+			offset += node.len();
+		}
+		Some(unsafe { &*(list.as_ptr() as *const DevicePath) })
+	}
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+	data[0] as u16 | (data[1] as u16) << 8
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+	read_u16(&data[0..2]) as u32 | (read_u16(&data[2..4]) as u32) << 16
+}
+
+fn write_u16(data: &mut Vec<u8>, value: u16) {
+	data.push(value as u8);
+	data.push((value >> 8) as u8);
+}
+
+fn write_u32(data: &mut Vec<u8>, value: u32) {
+	write_u16(data, value as u16);
+	write_u16(data, (value >> 16) as u16);
+}
+
+fn get_u16(name: &str) -> ::Result<u16> {
+	let mut data = [0; 2];
+	match try!(variable::get(name, &GLOBAL_VARIABLE, &mut data)) {
+		(2, _) => Ok(read_u16(&data)),
+		_ => Err(Status::VolumeCorrupted)
+	}
+}
+
+fn set_u16(name: &str, value: u16, attributes: VariableAttributes) -> ::Result<()> {
+	let data = [value as u8, (value >> 8) as u8];
+	variable::set(name, &GLOBAL_VARIABLE, attributes, &data)
+}
+
+pub fn load_option(kind: LoadOptionKind, number: u16) -> ::Result<LoadOption> {
+	let (data, _) = try!(variable::get_vec(&kind.variable(number), &GLOBAL_VARIABLE));
+	LoadOption::parse(&data)
+}
+
+pub fn set_load_option(kind: LoadOptionKind, number: u16, option: &LoadOption) -> ::Result<()> {
+	variable::set(&kind.variable(number), &GLOBAL_VARIABLE, attributes(), &option.to_bytes())
+}
+
+// also takes the number out of the order
+pub fn delete_load_option(kind: LoadOptionKind, number: u16) -> ::Result<()> {
+	let mut order = try!(load_order(kind));
+	if let Some(index) = order.iter().position(|n| *n == number) {
+		order.remove(index);
+		try!(set_load_order(kind, &order));
+	}
+	match variable::delete(&kind.variable(number), &GLOBAL_VARIABLE) {
+		Ok(()) | Err(Status::NotFound) => Ok(()),
+		Err(error) => Err(error)
+	}
+}
+
+// stores the option under the first unused number and puts it at the end of the order, returns the number
+pub fn add_load_option(kind: LoadOptionKind, option: &LoadOption) -> ::Result<u16> {
+	let mut number = 0;
+	loop {
+		match variable::size(&kind.variable(number), &GLOBAL_VARIABLE) {
+			Err(Status::NotFound) => break,
+			Err(error) => return Err(error),
+			Ok(_) if number == 0xFFFF => return Err(Status::OutOfResources),
+			Ok(_) => number += 1
+		}
+	}
+	try!(set_load_option(kind, number, option));
+	let mut order = try!(load_order(kind));
+	order.push(number);
+	try!(set_load_order(kind, &order));
+	Ok(number)
+}
+
+// BootOrder, DriverOrder or SysPrepOrder, empty if the variable doesn't exist
+pub fn load_order(kind: LoadOptionKind) -> ::Result<Vec<u16>> {
+	let data = match variable::get_vec(&kind.order_variable(), &GLOBAL_VARIABLE) {
+		Ok((data, _)) => data,
+		Err(Status::NotFound) => return Ok(Vec::new()),
+		Err(error) => return Err(error)
+	};
+	parse_order(&data)
+}
+
+pub fn set_load_order(kind: LoadOptionKind, order: &[u16]) -> ::Result<()> {
+	variable::set(&kind.order_variable(), &GLOBAL_VARIABLE, attributes(), &order_bytes(order))
+}
+
+fn parse_order(data: &[u8]) -> ::Result<Vec<u16>> {
+	if data.len() % 2 != 0 {
+		return Err(Status::VolumeCorrupted);
+	}
+	Ok(data.chunks(2).map(read_u16).collect())
+}
+
+fn order_bytes(order: &[u16]) -> Vec<u8> {
+	let mut data = Vec::with_capacity(order.len() * 2);
+	for number in order {
+		write_u16(&mut data, *number);
+	}
+	data
+}
+
+// the Boot#### this boot went through, set by the boot manager and read only
+pub fn boot_current() -> ::Result<u16> {
+	get_u16("BootCurrent")
+}
+
+// a one-time override of BootOrder for the next boot
+pub fn boot_next() -> ::Result<Option<u16>> {
+	match get_u16("BootNext") {
+		Ok(number) => Ok(Some(number)),
+		Err(Status::NotFound) => Ok(None),
+		Err(error) => Err(error)
+	}
+}
+
+pub fn set_boot_next(number: Option<u16>) -> ::Result<()> {
+	match number {
+		Some(number) => set_u16("BootNext", number, attributes()),
+		None => match variable::delete("BootNext", &GLOBAL_VARIABLE) {
+			Ok(()) | Err(Status::NotFound) => Ok(()),
+			Err(error) => Err(error)
+		}
+	}
+}
+
+// seconds the boot manager waits before booting, 0xFFFF means wait for the user
+pub fn timeout() -> ::Result<u16> {
+	get_u16("Timeout")
+}
+
+pub fn set_timeout(seconds: u16) -> ::Result<()> {
+	set_u16("Timeout", seconds, attributes())
+}
+
+#[cfg(test)]
+mod tests {
+	use core::prelude::*;
+	use collections::String;
+
+	use Status;
+	use super::*;
+	use super::{parse_order, order_bytes};
+
+	// Boot0000 as OVMF writes it: the UiApp setup application, active, hidden and in the app
+	// category, a firmware volume node, a firmware file node and the end node, no optional data
+	const UI_APP: [u8; 62] = [
+		0x09, 0x01, 0x00, 0x00, // attributes
+		0x2C, 0x00, // file path list length
+		0x55, 0x00, 0x69, 0x00, 0x41, 0x00, 0x70, 0x00, 0x70, 0x00, 0x00, 0x00, // "UiApp"
+		0x04, 0x07, 0x14, 0x00, // fv 7CB8BDC9-F8EB-4F34-AAEA-3EE4AF6516A1
+		0xC9, 0xBD, 0xB8, 0x7C, 0xEB, 0xF8, 0x34, 0x4F, 0xAA, 0xEA, 0x3E, 0xE4, 0xAF, 0x65, 0x16, 0xA1,
+		0x04, 0x06, 0x14, 0x00, // fv file 462CAA21-7614-4503-836E-8AB6F4662331
+		0x21, 0xAA, 0x2C, 0x46, 0x14, 0x76, 0x03, 0x45, 0x83, 0x6E, 0x8A, 0xB6, 0xF4, 0x66, 0x23, 0x31,
+		0x7F, 0xFF, 0x04, 0x00 // end
+	];
+
+	// Boot0002, a shell on the esp added by efibootmgr, with a file path node and 16 bytes of
+	// optional data
+	const SHELL: [u8; 64] = [
+		0x01, 0x00, 0x00, 0x00, // attributes
+		0x1E, 0x00, // file path list length
+		0x53, 0x00, 0x68, 0x00, 0x65, 0x00, 0x6C, 0x00, 0x6C, 0x00, 0x00, 0x00, // "Shell"
+		0x04, 0x04, 0x1A, 0x00, // file path "\shell.efi"
+		0x5C, 0x00, 0x73, 0x00, 0x68, 0x00, 0x65, 0x00, 0x6C, 0x00, 0x6C, 0x00,
+		0x2E, 0x00, 0x65, 0x00, 0x66, 0x00, 0x69, 0x00, 0x00, 0x00,
+		0x7F, 0xFF, 0x04, 0x00, // end
+		0x4E, 0xAC, 0x08, 0x81, 0x11, 0x9F, 0x59, 0x4D, 0x85, 0x0E, 0xE2, 0x1A, 0x52, 0x2C, 0x59, 0xB2
+	];
+
+	// BootOrder: 0001, 0000, 0002
+	const BOOT_ORDER: [u8; 6] = [0x01, 0x00, 0x00, 0x00, 0x02, 0x00];
+
+	#[test]
+	fn parse_ui_app() {
+		let option = LoadOption::parse(&UI_APP).unwrap();
+		assert_eq!(option.attributes, LOAD_OPTION_ACTIVE | LOAD_OPTION_HIDDEN | LoadOptionAttributes::from_bits(0x100));
+		assert_eq!(option.description, String::from("UiApp"));
+		assert_eq!(option.file_path_list, UI_APP[18..].to_vec());
+		assert!(option.optional_data.is_empty());
+		assert!(option.is_active());
+		assert_eq!(option.category(), LoadOptionCategory::App);
+		assert_eq!(option.file_path().unwrap().total_len(), 44);
+	}
+
+	#[test]
+	fn parse_shell() {
+		let option = LoadOption::parse(&SHELL).unwrap();
+		assert_eq!(option.attributes, LOAD_OPTION_ACTIVE);
+		assert_eq!(option.description, String::from("Shell"));
+		assert_eq!(option.file_path_list, SHELL[18..48].to_vec());
+		assert_eq!(option.optional_data, SHELL[48..].to_vec());
+		assert_eq!(option.category(), LoadOptionCategory::Boot);
+		assert_eq!(option.file_path().unwrap().total_len(), 30);
+	}
+
+	#[test]
+	fn round_trip() {
+		assert_eq!(LoadOption::parse(&UI_APP).unwrap().to_bytes(), UI_APP.to_vec());
+		assert_eq!(LoadOption::parse(&SHELL).unwrap().to_bytes(), SHELL.to_vec());
+	}
+
+	#[test]
+	fn truncated() {
+		// anything short of the end of the file path list is cut off somewhere that matters
+		for length in 0..UI_APP.len() {
+			assert_eq!(LoadOption::parse(&UI_APP[..length]), Err(Status::VolumeCorrupted));
+		}
+		for length in 0..48 {
+			assert_eq!(LoadOption::parse(&SHELL[..length]), Err(Status::VolumeCorrupted));
+		}
+		// cutting into the optional data just leaves less of it
+		assert_eq!(LoadOption::parse(&SHELL[..50]).unwrap().optional_data, SHELL[48..50].to_vec());
+	}
+
+	#[test]
+	fn malformed_file_paths() {
+		let mut option = LoadOption::parse(&UI_APP).unwrap();
+		// a node of length 0 would never get anywhere
+		option.file_path_list[22] = 0x00;
+		assert!(option.file_path().is_none());
+
+		// a node running past the end of the list
+		let mut option = LoadOption::parse(&UI_APP).unwrap();
+		option.file_path_list[22] = 0x40;
+		assert!(option.file_path().is_none());
+
+		// no end node
+		let mut option = LoadOption::parse(&UI_APP).unwrap();
+		option.file_path_list.truncate(40);
+		assert!(option.file_path().is_none());
+
+		option.file_path_list.clear();
+		assert!(option.file_path().is_none());
+	}
+
+	#[test]
+	fn category() {
+		let mut option = LoadOption::parse(&SHELL).unwrap();
+		option.set_category(LoadOptionCategory::App);
+		assert_eq!(option.category(), LoadOptionCategory::App);
+		assert!(option.is_active());
+		option.set_category(LoadOptionCategory::Boot);
+		assert_eq!(option.attributes, LOAD_OPTION_ACTIVE);
+		option.attributes = LoadOptionAttributes::from_bits(0x201);
+		assert_eq!(option.category(), LoadOptionCategory::Reserved(0x200));
+	}
+
+	#[test]
+	fn boot_order() {
+		let order = parse_order(&BOOT_ORDER).unwrap();
+		assert_eq!(order, vec![1, 0, 2]);
+		assert_eq!(order_bytes(&order), BOOT_ORDER.to_vec());
+		assert_eq!(parse_order(&BOOT_ORDER[..5]), Err(Status::VolumeCorrupted));
+		assert_eq!(parse_order(&[]), Ok(vec![]));
+	}
+}
@@ -0,0 +1,122 @@
+use core::prelude::*;
+use core::slice;
+use alloc::boxed::Box;
+
+use ::{Status, Guid, Handle};
+use protocol::{Protocol, DevicePath};
+use interface::Installed;
+use super::{to_status, device_path};
+
+// the firmware calls these when it connects controllers, supported() gets called a lot and should be
+// cheap and not leave anything open, start() and stop() do the actual work
+pub trait DriverBinding {
+	fn supported(&self, controller: Handle, remaining_path: Option<&DevicePath>) -> ::Result<()>;
+	fn start(&self, controller: Handle, remaining_path: Option<&DevicePath>) -> ::Result<()>;
+	fn stop(&self, controller: Handle, children: &[Handle]) -> ::Result<()>;
+}
+
+#[repr(C)]
+pub struct DriverBindingProtocol {
+	supported: efi_fn!(*const DriverBindingProtocol, Handle, *const DevicePath),
+	start: efi_fn!(*const DriverBindingProtocol, Handle, *const DevicePath),
+	stop: efi_fn!(*const DriverBindingProtocol, Handle, usize, *const Handle),
+	version: u32,
+	image_handle: Handle,
+	driver_binding_handle: Handle,
+
+	// only we ever look past the firmware's part of the struct
+	driver: Box<DriverBinding + 'static>
+}
+
+impl Protocol for DriverBindingProtocol {
+	fn guid() -> Guid {
+		Guid(0x18A031AB, 0xB443, 0x4D1A, 0xA5, 0xC0, 0x0C, 0x09, 0x26, 0x1E, 0x9F, 0x71)
+	}
+}
+
+impl DriverBindingProtocol {
+	pub fn version(&self) -> u32 {
+		self.version
+	}
+
+	pub fn image_handle(&self) -> Handle {
+		self.image_handle
+	}
+
+	pub fn driver_binding_handle(&self) -> Handle {
+		self.driver_binding_handle
+	}
+}
+
+efi_callback! {
+	fn supported(this: *const DriverBindingProtocol, controller: Handle, remaining_path: *const DevicePath) -> usize {
+		let this = unsafe { &*this };
+		to_status(this.driver.supported(controller, device_path(remaining_path))).0
+	}
+}
+
+efi_callback! {
+	fn start(this: *const DriverBindingProtocol, controller: Handle, remaining_path: *const DevicePath) -> usize {
+		let this = unsafe { &*this };
+		to_status(this.driver.start(controller, device_path(remaining_path))).0
+	}
+}
+
+efi_callback! {
+	fn stop(this: *const DriverBindingProtocol, controller: Handle, child_count: usize, children: *const Handle) -> usize {
+		let this = unsafe { &*this };
+		let children: &[Handle] = if children.is_null() {
+			&[]
+		} else {
+			unsafe { slice::from_raw_parts(children, child_count) }
+		};
+		to_status(this.driver.stop(controller, children)).0
+	}
+}
+
+// installs the binding on our image handle, which is where the firmware expects it. a higher version
+// wins when several drivers support the same controller (0x10 is the usual starting point).
+// drivers that stay resident after their entry point returns should leak() the result
+pub fn install<T: DriverBinding + 'static>(driver: T, version: u32) -> ::Result<Installed<DriverBindingProtocol>> {
+	install_on(::get_current_image(), driver, version)
+}
+
+// for drivers that produce more than one binding, the extra ones go on new handles (pass Handle::null())
+pub fn install_on<T: DriverBinding + 'static>(handle: Handle, driver: T, version: u32) -> ::Result<Installed<DriverBindingProtocol>> {
+	let mut installed = try!(Installed::install(Some(handle), DriverBindingProtocol {
+		supported: supported,
+		start: start,
+		stop: stop,
+		version: version,
+		image_handle: ::get_current_image(),
+		driver_binding_handle: handle,
+		driver: Box::new(driver)
+	}));
+	// the firmware opens controllers BY_DRIVER with this handle, so it has to be the real one.
+	// installing doesn't connect anything, so nothing has seen the null one yet
+	let handle = installed.handle();
+	installed.interface_mut().driver_binding_handle = handle;
+	Ok(installed)
+}
+
+// the firmware's part ends at driver_binding_handle, the box follows it without padding
+#[allow(dead_code)]
+fn assert_layout() {
+	assert_size!(DriverBindingProtocol, 24 + 8, 48 + 16); // prefix
+}
+
+#[cfg(test)]
+mod tests {
+	use super::DriverBindingProtocol;
+
+	#[test]
+	fn layout() {
+		assert_offset!(DriverBindingProtocol, supported, 0, 0);
+		assert_offset!(DriverBindingProtocol, start, 4, 8);
+		assert_offset!(DriverBindingProtocol, stop, 8, 16);
+		assert_offset!(DriverBindingProtocol, version, 12, 24);
+		assert_offset!(DriverBindingProtocol, image_handle, 16, 32);
+		assert_offset!(DriverBindingProtocol, driver_binding_handle, 20, 40);
+		assert_offset!(DriverBindingProtocol, driver, 24, 48);
+	}
+}
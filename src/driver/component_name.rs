@@ -0,0 +1,148 @@
+use core::prelude::*;
+use core::cell::RefCell;
+use core::slice;
+use alloc::boxed::Box;
+use collections::{Vec, String};
+
+use ::{Status, Guid, Handle};
+use protocol::Protocol;
+use interface::Installed;
+
+// names shown in the firmware's driver and device listings. languages are RFC 4646 codes like "en"
+// or "en-US", the name functions only ever get asked for one of those
+pub trait ComponentName {
+	fn languages(&self) -> &[&str];
+	fn driver_name(&self, language: &str) -> Option<String>;
+	// child is set when the firmware asks about a child controller we created
+	fn controller_name(&self, controller: Handle, child: Option<Handle>, language: &str) -> Option<String>;
+}
+
+#[repr(C)]
+pub struct ComponentName2Protocol {
+	get_driver_name: efi_fn!(*const ComponentName2Protocol, *const u8, *mut *const u16),
+	get_controller_name: efi_fn!(*const ComponentName2Protocol, Handle, Handle, *const u8, *mut *const u16),
+	supported_languages: *const u8,
+
+	languages: Vec<u8>, // "en;fr", null terminated
+	names: RefCell<Vec<Vec<u16>>>, // the firmware keeps the pointers we give out, so every name stays here
+	component: Box<ComponentName + 'static>
+}
+
+impl Protocol for ComponentName2Protocol {
+	fn guid() -> Guid {
+		Guid(0x6A7A5CFF, 0xE8D9, 0x4F70, 0xBA, 0xDA, 0x75, 0xAB, 0x30, 0x25, 0xCE, 0x14)
+	}
+}
+
+impl ComponentName2Protocol {
+	// matches the requested language against ours, they're case insensitive
+	fn language(&self, requested: *const u8) -> Option<&str> {
+		if requested.is_null() {
+			return None;
+		}
+		let requested = unsafe {
+			let mut length = 0;
+			while *requested.offset(length) != 0 {
+				length += 1;
+			}
+			slice::from_raw_parts(requested, length as usize)
+		};
+		self.component.languages().iter().map(|language| *language).find(|language| {
+			language.len() == requested.len()
+				&& language.bytes().zip(requested.iter()).all(|(a, b)| to_lower(a) == to_lower(*b))
+		})
+	}
+
+	fn intern(&self, name: &str) -> *const u16 {
+		let name = ::to_utf16(name);
+		let mut names = self.names.borrow_mut();
+		if let Some(existing) = names.iter().find(|existing| **existing == name) {
+			return existing.as_ptr();
+		}
+		names.push(name);
+		names[names.len() - 1].as_ptr()
+	}
+}
+
+fn to_lower(c: u8) -> u8 {
+	if c >= b'A' && c <= b'Z' { c + 32 } else { c }
+}
+
+efi_callback! {
+	fn get_driver_name(this: *const ComponentName2Protocol, language: *const u8, name: *mut *const u16) -> usize {
+		let this = unsafe { &*this };
+		let language = match this.language(language) {
+			Some(language) => language,
+			None => return Status::Unsupported.0
+		};
+		match this.component.driver_name(language) {
+			Some(driver_name) => {
+				unsafe { *name = this.intern(&driver_name); }
+				Status::Success.0
+			},
+			None => Status::Unsupported.0
+		}
+	}
+}
+
+efi_callback! {
+	fn get_controller_name(this: *const ComponentName2Protocol, controller: Handle, child: Handle, language: *const u8, name: *mut *const u16) -> usize {
+		let this = unsafe { &*this };
+		let language = match this.language(language) {
+			Some(language) => language,
+			None => return Status::Unsupported.0
+		};
+		let child = if child.is_null() { None } else { Some(child) };
+		match this.component.controller_name(controller, child, language) {
+			Some(controller_name) => {
+				unsafe { *name = this.intern(&controller_name); }
+				Status::Success.0
+			},
+			None => Status::Unsupported.0
+		}
+	}
+}
+
+// goes on the same handle as the driver binding, our image handle unless install_on was used there
+pub fn install<T: ComponentName + 'static>(component: T) -> ::Result<Installed<ComponentName2Protocol>> {
+	install_on(::get_current_image(), component)
+}
+
+pub fn install_on<T: ComponentName + 'static>(handle: Handle, component: T) -> ::Result<Installed<ComponentName2Protocol>> {
+	let mut languages = Vec::new();
+	for (i, language) in component.languages().iter().enumerate() {
+		if i > 0 {
+			languages.push(b';');
+		}
+		languages.extend(language.bytes());
+	}
+	languages.push(0);
+	Installed::install(Some(handle), ComponentName2Protocol {
+		get_driver_name: get_driver_name,
+		get_controller_name: get_controller_name,
+		supported_languages: languages.as_ptr(),
+		languages: languages,
+		names: RefCell::new(Vec::new()),
+		component: Box::new(component)
+	})
+}
+
+// the firmware's part ends at supported_languages, what follows is ours: the language list, the
+// interned names behind a RefCell and the component
+#[allow(dead_code)]
+fn assert_layout() {
+	assert_size!(ComponentName2Protocol, 12 + 36, 24 + 72); // prefix
+}
+
+#[cfg(test)]
+mod tests {
+	use super::ComponentName2Protocol;
+
+	#[test]
+	fn layout() {
+		assert_offset!(ComponentName2Protocol, get_driver_name, 0, 0);
+		assert_offset!(ComponentName2Protocol, get_controller_name, 4, 8);
+		assert_offset!(ComponentName2Protocol, supported_languages, 8, 16);
+		assert_offset!(ComponentName2Protocol, languages, 12, 24);
+	}
+}
@@ -0,0 +1,36 @@
+use core::prelude::*;
+
+use ::{Status, Handle};
+use protocol::DevicePath;
+
+pub mod binding;
+pub mod component_name;
+
+pub use self::binding::{DriverBinding, DriverBindingProtocol};
+pub use self::component_name::{ComponentName, ComponentName2Protocol};
+
+// makes the firmware connect every driver it can to every controller, recursively, which is how you
+// get all the disks and network interfaces to show up when the boot manager didn't bother
+pub fn connect_all() -> ::Result<()> {
+	let handles = try!(::get_boot_services().all_handles());
+	for handle in handles.iter() {
+		// most handles aren't controllers, those just come back with NotFound
+		let _ = handle.connect(true);
+	}
+	Ok(())
+}
+
+fn to_status(result: ::Result<()>) -> Status {
+	match result {
+		Ok(()) => Status::Success,
+		Err(error) => error
+	}
+}
+
+fn device_path<'a>(path: *const DevicePath) -> Option<&'a DevicePath> {
+	if path.is_null() {
+		None
+	} else {
+		Some(unsafe { &*path })
+	}
+}
@@ -3,37 +3,52 @@ use Table;
 use Handle;
 
 #[macro_export]
-macro_rules! efi_main {($name:ident) => {
-	#[no_mangle]
-	pub extern "C" fn rust_efi_main() -> $crate::Status {
-		$name()
+macro_rules! efi_main {
+	($name:ident) => {
+		#[no_mangle]
+		pub extern "C" fn rust_efi_main() -> usize {
+			$name().0
+		}
+	};
+	// for long running tools, the firmware's watchdog would otherwise reset the machine after 5 minutes
+	($name:ident, disable_watchdog) => {
+		#[no_mangle]
+		pub extern "C" fn rust_efi_main() -> usize {
+			let _ = $crate::watchdog::disable();
+			$name().0
+		}
 	}
-}}
+}
 
+#[cfg(not(test))]
 extern "C" {
-	fn rust_efi_main() -> ::Status;
+	fn rust_efi_main() -> usize;
 }
 
-#[cfg(target_pointer_width="32")]
+#[cfg(all(target_pointer_width="32", not(test)))]
 #[no_mangle]
-pub extern "system" fn efi_main(image: Handle, system_table: &'static Table<table::System<'static>>) -> ::Status {
+pub extern "system" fn efi_main(image: Handle, system_table: &'static Table<table::System<'static>>) -> usize {
 	unsafe {
 		::system_table = system_table as *const Table<table::System>;
 		::boot_services = system_table.get_boot_services();
 		::runtime_services = system_table.get_runtime_services();
 		::current_image = image;
-		rust_efi_main()
+		let status = rust_efi_main();
+		::shutdown::run_hooks();
+		status
 	}
 }
 
-#[cfg(target_pointer_width="64")]
+#[cfg(all(target_pointer_width="64", not(test)))]
 #[no_mangle]
-pub extern "win64" fn efi_main(image: Handle, system_table: &'static Table<table::System<'static>>) -> ::Status {
+pub extern "win64" fn efi_main(image: Handle, system_table: &'static Table<table::System<'static>>) -> usize {
 	unsafe {
 		::system_table = system_table as *const Table<table::System>;
 		::boot_services = system_table.get_boot_services();
 		::runtime_services = system_table.get_runtime_services();
 		::current_image = image;
-		rust_efi_main()
+		let status = rust_efi_main();
+		::shutdown::run_hooks();
+		status
 	}
 }
@@ -1,7 +1,163 @@
+use core::prelude::*;
+use core::ptr;
+use alloc::boxed::Box;
+use collections::Vec;
+
+use table::{Tpl, TimerType};
+use ::{Status, Guid};
+
+efi_flags!(EventType: u32 {
+	EVT_TIMER = 0x80000000,
+	EVT_RUNTIME = 0x40000000,
+	EVT_NOTIFY_WAIT = 0x00000100,
+	EVT_NOTIFY_SIGNAL = 0x00000200,
+	EVT_SIGNAL_EXIT_BOOT_SERVICES = 0x00000201,
+	EVT_SIGNAL_VIRTUAL_ADDRESS_CHANGE = 0x60000202,
+});
+
+// the standard groups for create_event_ex/Event::in_group
+pub const EVENT_GROUP_EXIT_BOOT_SERVICES: Guid = Guid(0x27ABF055, 0xB1B8, 0x4C26, 0x80, 0x48, 0x74, 0x8F, 0x37, 0xBA, 0xA2, 0xDF);
+pub const EVENT_GROUP_BEFORE_EXIT_BOOT_SERVICES: Guid = Guid(0x8BE0E274, 0x3970, 0x4B44, 0x80, 0xC5, 0x1A, 0xB9, 0x50, 0x2F, 0x3B, 0xFC);
+pub const EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE: Guid = Guid(0x13FA7698, 0xC831, 0x49C7, 0x87, 0xEA, 0x8F, 0x43, 0xFC, 0xC2, 0x51, 0x96);
+pub const EVENT_GROUP_MEMORY_MAP_CHANGE: Guid = Guid(0x78BEE926, 0x692F, 0x48FD, 0x9E, 0xDB, 0x01, 0x42, 0x2E, 0xF0, 0xD7, 0xAB);
+pub const EVENT_GROUP_READY_TO_BOOT: Guid = Guid(0x7CE88FB3, 0x4BD7, 0x4679, 0x87, 0xA8, 0xA8, 0xD8, 0xDE, 0xE5, 0x0D, 0x2B);
+
+type Notify = Box<FnMut() + 'static>;
+
+efi_callback! {
+	fn notify_trampoline(_event: *const (), context: *mut ()) {
+		let notify = unsafe { &mut *(context as *mut Notify) };
+		(**notify)();
+	}
+}
+
 pub struct Event {
-	_ptr: *const ()
+	_ptr: *const (),
+	notify: Option<Box<Notify>> // the firmware holds a pointer to this, so it lives as long as the event
 }
 
+impl Event {
+	pub fn new(typ: EventType) -> ::Result<Event> {
+		let ptr = try!(unsafe { ::get_boot_services().create_event(typ, Tpl::Application, None, ptr::null_mut()) });
+		Ok(Event {
+			_ptr: ptr,
+			notify: None
+		})
+	}
+
+	// notify runs at tpl, which has to be Callback or Notify, so keep it short and don't wait in it
+	pub fn with_notify<F: FnMut() + 'static>(typ: EventType, tpl: Tpl, notify: F) -> ::Result<Event> {
+		Event::create(typ, tpl, Box::new(notify), None)
+	}
+
+	// notify runs whenever any event in the group is signalled, for the standard groups that means
+	// when the firmware gets to that point (exit_boot_services, ready to boot...)
+	pub fn in_group<F: FnMut() + 'static>(group: Guid, tpl: Tpl, notify: F) -> ::Result<Event> {
+		Event::create(EVT_NOTIFY_SIGNAL, tpl, Box::new(notify), Some(&group))
+	}
+
+	fn create(typ: EventType, tpl: Tpl, notify: Notify, group: Option<&Guid>) -> ::Result<Event> {
+		if tpl != Tpl::Callback && tpl != Tpl::Notify {
+			return Err(Status::InvalidParameter);
+		}
+		let mut notify: Box<Notify> = Box::new(notify);
+		let context = &mut *notify as *mut Notify as *mut ();
+		let ptr = try!(unsafe { ::get_boot_services().create_event_ex(typ, tpl, Some(notify_trampoline), context, group) });
+		Ok(Event {
+			_ptr: ptr,
+			notify: Some(notify)
+		})
+	}
+
+	// takes ownership of an event created elsewhere, it gets closed on drop
+	pub unsafe fn from_raw(ptr: *const ()) -> Event {
+		Event {
+			_ptr: ptr,
+			notify: None
+		}
+	}
+
+	pub fn as_raw(&self) -> *const () {
+		self._ptr
+	}
+
+	pub fn signal(&self) -> ::Result<()> {
+		unsafe { ::get_boot_services().signal_event(self._ptr) }.into_result()
+	}
+
+	// true if the event was signalled, this also resets it
+	pub fn check(&self) -> ::Result<bool> {
+		match unsafe { ::get_boot_services().check_event(self._ptr) } {
+			Status::Success => Ok(true),
+			Status::NotReady => Ok(false),
+			error => Err(error)
+		}
+	}
+
+	pub fn wait(&self) -> ::Result<()> {
+		try!(unsafe { ::get_boot_services().wait_for_event(&[self._ptr]) });
+		Ok(())
+	}
+}
+
+impl Drop for Event {
+	fn drop(&mut self) {
+		if ::boot_services_active() {
+			unsafe {
+				::get_boot_services().close_event(self._ptr);
+			}
+		}
+	}
+}
+
+// signals every event in the group, for custom groups shared between drivers
+pub fn signal_group(group: Guid) -> ::Result<()> {
+	let event = try!(Event::in_group(group, Tpl::Callback, || { }));
+	event.signal()
+}
+
+// blocks until one of the events is signalled and returns its index, only valid at Tpl::Application
+pub fn wait_for_any(events: &[&Event]) -> ::Result<usize> {
+	let raw: Vec<*const ()> = events.iter().map(|event| event._ptr).collect();
+	unsafe { ::get_boot_services().wait_for_event(&raw) }
+}
+
+// all times are in units of 100ns
 pub struct Timer {
 	event: Event
 }
+
+impl Timer {
+	pub fn new() -> ::Result<Timer> {
+		Ok(Timer {
+			event: try!(Event::new(EVT_TIMER))
+		})
+	}
+
+	pub fn with_notify<F: FnMut() + 'static>(tpl: Tpl, notify: F) -> ::Result<Timer> {
+		Ok(Timer {
+			event: try!(Event::with_notify(EVT_TIMER | EVT_NOTIFY_SIGNAL, tpl, notify))
+		})
+	}
+
+	pub fn event(&self) -> &Event {
+		&self.event
+	}
+
+	pub fn one_shot(&self, delay: u64) -> ::Result<()> {
+		unsafe { ::get_boot_services().set_timer(self.event._ptr, TimerType::Relative, delay) }.into_result()
+	}
+
+	// a period of 0 fires on every timer tick
+	pub fn periodic(&self, period: u64) -> ::Result<()> {
+		unsafe { ::get_boot_services().set_timer(self.event._ptr, TimerType::Periodic, period) }.into_result()
+	}
+
+	pub fn cancel(&self) -> ::Result<()> {
+		unsafe { ::get_boot_services().set_timer(self.event._ptr, TimerType::Cancel, 0) }.into_result()
+	}
+
+	pub fn wait(&self) -> ::Result<()> {
+		self.event.wait()
+	}
+}
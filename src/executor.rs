@@ -0,0 +1,212 @@
+use core::prelude::*;
+use core::cell::{Cell, RefCell};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use collections::Vec;
+
+use event::{self, Event, Timer};
+use table::Tpl;
+use tpl;
+
+pub enum Poll<T> {
+	Ready(T),
+	Pending
+}
+
+pub trait Future {
+	type Output;
+
+	fn poll(&mut self, cx: &mut Context) -> Poll<Self::Output>;
+}
+
+// signals the executor's wake event, so it can be used from notify functions (up to Tpl::Notify).
+// it shares the event with the executor, so a waker outliving its executor still signals a live
+// event, just one nobody waits on anymore
+#[derive(Clone)]
+pub struct Waker {
+	event: Rc<Event>
+}
+
+impl Waker {
+	pub fn wake(&self) {
+		let _ = self.event.signal();
+	}
+}
+
+pub struct Context<'a> {
+	waker: Waker,
+	waits: &'a mut Vec<*const ()>
+}
+
+impl<'a> Context<'a> {
+	pub fn waker(&self) -> Waker {
+		self.waker.clone()
+	}
+
+	// for events that can't have a notify function (e.g. WaitForKey), the executor waits on them directly.
+	// the event that ends a wait gets signalled again for its future to see, so the future has to clear
+	// it (Event::check) each time it is polled, or the executor never sleeps again
+	pub fn wait_on(&mut self, event: &Event) {
+		self.waits.push(event.as_raw());
+	}
+}
+
+// single-threaded, every wakeup polls all tasks, which is plenty for a boot processor
+pub struct Executor {
+	wake: Rc<Event>,
+	tasks: Vec<Box<Future<Output=()>>>
+}
+
+impl Executor {
+	pub fn new() -> ::Result<Executor> {
+		Ok(Executor {
+			wake: Rc::new(try!(Event::new(event::EventType::empty()))),
+			tasks: Vec::new()
+		})
+	}
+
+	pub fn spawn<F: Future<Output=()> + 'static>(&mut self, future: F) {
+		self.tasks.push(Box::new(future));
+	}
+
+	// drives future to completion, spawned tasks make progress in the meantime
+	pub fn block_on<F: Future>(&mut self, mut future: F) -> ::Result<F::Output> {
+		let mut waits = Vec::new();
+		loop {
+			waits.clear();
+			{
+				let mut cx = Context {
+					waker: Waker { event: self.wake.clone() },
+					waits: &mut waits
+				};
+				if let Poll::Ready(value) = future.poll(&mut cx) {
+					return Ok(value);
+				}
+				self.poll_tasks(&mut cx);
+			}
+			try!(self.sleep(&mut waits));
+		}
+	}
+
+	// runs until every spawned task has finished
+	pub fn run(&mut self) -> ::Result<()> {
+		let mut waits = Vec::new();
+		loop {
+			waits.clear();
+			{
+				let mut cx = Context {
+					waker: Waker { event: self.wake.clone() },
+					waits: &mut waits
+				};
+				self.poll_tasks(&mut cx);
+			}
+			if self.tasks.is_empty() {
+				return Ok(());
+			}
+			try!(self.sleep(&mut waits));
+		}
+	}
+
+	fn poll_tasks(&mut self, cx: &mut Context) {
+		let mut i = 0;
+		while i < self.tasks.len() {
+			match self.tasks[i].poll(cx) {
+				Poll::Ready(()) => {
+					self.tasks.swap_remove(i);
+				},
+				Poll::Pending => i += 1
+			}
+		}
+	}
+
+	fn sleep(&self, waits: &mut Vec<*const ()>) -> ::Result<()> {
+		// a waker may have fired while we were polling
+		if try!(self.wake.check()) {
+			return Ok(());
+		}
+		waits.push(self.wake.as_raw());
+		let index = try!(unsafe { ::get_boot_services().wait_for_event(waits) });
+		if index != waits.len() - 1 {
+			// waiting reset the event, signal it again so the future that registered it sees it
+			unsafe {
+				::get_boot_services().signal_event(waits[index]);
+			}
+		}
+		Ok(())
+	}
+}
+
+pub fn block_on<F: Future>(future: F) -> ::Result<F::Output> {
+	try!(Executor::new()).block_on(future)
+}
+
+struct DelayState {
+	fired: Cell<bool>,
+	waker: RefCell<Option<Waker>> // only set at Tpl::Callback, so the notify never finds it borrowed
+}
+
+// a one-shot timer future, the duration is in units of 100ns
+pub struct Delay {
+	_timer: Timer,
+	state: Rc<DelayState>
+}
+
+pub fn delay(duration: u64) -> ::Result<Delay> {
+	let state = Rc::new(DelayState {
+		fired: Cell::new(false),
+		waker: RefCell::new(None)
+	});
+	let notify_state = state.clone();
+	let timer = try!(Timer::with_notify(Tpl::Callback, move || {
+		notify_state.fired.set(true);
+		if let Some(ref waker) = *notify_state.waker.borrow() {
+			waker.wake();
+		}
+	}));
+	try!(timer.one_shot(duration));
+	Ok(Delay {
+		_timer: timer,
+		state: state
+	})
+}
+
+impl Future for Delay {
+	type Output = ();
+
+	fn poll(&mut self, cx: &mut Context) -> Poll<()> {
+		// store the waker first so a notify landing in between isn't lost
+		let waker = cx.waker();
+		tpl::critical_section(Tpl::Callback, || *self.state.waker.borrow_mut() = Some(waker));
+		if self.state.fired.get() {
+			Poll::Ready(())
+		} else {
+			Poll::Pending
+		}
+	}
+}
+
+// completes once a wait-type event (one without a notify function of ours) is signalled
+pub struct WaitEvent<'a> {
+	event: &'a Event
+}
+
+pub fn wait_event<'a>(event: &'a Event) -> WaitEvent<'a> {
+	WaitEvent {
+		event: event
+	}
+}
+
+impl<'a> Future for WaitEvent<'a> {
+	type Output = ::Result<()>;
+
+	fn poll(&mut self, cx: &mut Context) -> Poll<::Result<()>> {
+		match self.event.check() {
+			Ok(true) => Poll::Ready(Ok(())),
+			Ok(false) => {
+				cx.wait_on(self.event);
+				Poll::Pending
+			},
+			Err(error) => Poll::Ready(Err(error))
+		}
+	}
+}
@@ -0,0 +1,264 @@
+use core::prelude::*;
+use core::cmp::min;
+use core::mem::size_of;
+
+use table::{MemoryMap, MemoryType, PAGE_SIZE};
+
+// physical frame allocator for use after exit_boot_services, the bitmap is supplied by the caller
+// since there is no heap by then (allocating it as LoaderData beforehand works fine)
+pub struct FrameAllocator<'a> {
+	bitmap: &'a mut [u64], // a set bit means the frame is free
+	free: usize,
+	next: usize
+}
+
+fn is_free_type(typ: MemoryType) -> bool {
+	match typ {
+		MemoryType::Conventional
+			| MemoryType::BootServicesCode | MemoryType::BootServicesData
+			| MemoryType::LoaderCode | MemoryType::LoaderData => true,
+		_ => false
+	}
+}
+
+impl<'a> FrameAllocator<'a> {
+	// number of u64s the bitmap needs to cover every usable frame in the map
+	pub fn bitmap_words(map: &MemoryMap) -> usize {
+		let end = map.iter().filter(|descriptor| is_free_type(descriptor.memory_type())).fold(0, |end, descriptor| {
+			if descriptor.end() > end { descriptor.end() } else { end }
+		});
+		((end / PAGE_SIZE + 63) / 64) as usize
+	}
+
+	// reserved lists (start, size) ranges the kernel still needs (its own image, boot info, initrd...),
+	// they are taken out before the allocator is handed back so none of them can be allocated. the
+	// bitmap and the map's own buffer are reserved automatically
+	pub fn new(map: &MemoryMap, bitmap: &'a mut [u64], reserved: &[(u64, u64)]) -> FrameAllocator<'a> {
+		for word in bitmap.iter_mut() {
+			*word = 0;
+		}
+		let mut allocator = FrameAllocator {
+			bitmap: bitmap,
+			free: 0,
+			next: 0
+		};
+		for descriptor in map.iter() {
+			if is_free_type(descriptor.memory_type()) {
+				allocator.mark_range(descriptor.phys / PAGE_SIZE, descriptor.count, true);
+			}
+		}
+
+		// firmware memory is identity mapped, so these addresses are physical addresses
+		let bitmap_start = allocator.bitmap.as_ptr() as u64;
+		let bitmap_size = (allocator.bitmap.len() * size_of::<u64>()) as u64;
+		allocator.reserve(bitmap_start, bitmap_size);
+		allocator.reserve(map.as_ptr() as u64, map.byte_size() as u64);
+		for &(start, size) in reserved {
+			allocator.reserve(start, size);
+		}
+		// keep null pointers null
+		allocator.reserve(0, PAGE_SIZE);
+		allocator
+	}
+
+	pub fn frame_count(&self) -> u64 {
+		self.bitmap.len() as u64 * 64
+	}
+
+	pub fn free_frames(&self) -> usize {
+		self.free
+	}
+
+	pub fn reserve(&mut self, start: u64, size: u64) {
+		let first = start / PAGE_SIZE;
+		let last = (start + size + PAGE_SIZE - 1) / PAGE_SIZE;
+		self.mark_range(first, last - first, false);
+	}
+
+	pub fn alloc(&mut self) -> Option<u64> {
+		self.alloc_contiguous(1)
+	}
+
+	pub fn alloc_contiguous(&mut self, count: usize) -> Option<u64> {
+		let limit = self.frame_count();
+		let hint = self.next;
+		match self.find_run(hint, count, limit) {
+			Some(frame) => Some(self.take(frame, count)),
+			None => match self.find_run(0, count, limit) {
+				Some(frame) => Some(self.take(frame, count)),
+				None => None
+			}
+		}
+	}
+
+	// for devices that can only do 32-bit DMA and the like, limit is exclusive
+	pub fn alloc_below(&mut self, count: usize, limit: u64) -> Option<u64> {
+		let limit = min(limit / PAGE_SIZE, self.frame_count());
+		match self.find_run(0, count, limit) {
+			Some(frame) => Some(self.take(frame, count)),
+			None => None
+		}
+	}
+
+	pub fn alloc_below_4g(&mut self, count: usize) -> Option<u64> {
+		self.alloc_below(count, 0x100000000)
+	}
+
+	pub fn free(&mut self, address: u64, count: usize) {
+		self.mark_range(address / PAGE_SIZE, count as u64, true);
+	}
+
+	fn is_frame_free(&self, frame: u64) -> bool {
+		self.bitmap[(frame / 64) as usize] & (1 << (frame % 64)) != 0
+	}
+
+	fn mark_range(&mut self, first: u64, count: u64, free: bool) {
+		let end = min(first + count, self.frame_count());
+		let mut frame = first;
+		while frame < end {
+			let was_free = self.is_frame_free(frame);
+			let word = &mut self.bitmap[(frame / 64) as usize];
+			if free && !was_free {
+				*word |= 1 << (frame % 64);
+				self.free += 1;
+			} else if !free && was_free {
+				*word &= !(1 << (frame % 64));
+				self.free -= 1;
+			}
+			frame += 1;
+		}
+	}
+
+	fn find_run(&self, start: usize, count: usize, limit: u64) -> Option<u64> {
+		if count == 0 {
+			return None;
+		}
+		let mut run_start = start as u64;
+		let mut run_length = 0;
+		let mut frame = start as u64;
+		while frame < limit {
+			// skip over fully allocated words quickly
+			if run_length == 0 && frame % 64 == 0 && self.bitmap[(frame / 64) as usize] == 0 {
+				frame += 64;
+				continue;
+			}
+			if self.is_frame_free(frame) {
+				if run_length == 0 {
+					run_start = frame;
+				}
+				run_length += 1;
+				if run_length == count {
+					return Some(run_start);
+				}
+			} else {
+				run_length = 0;
+			}
+			frame += 1;
+		}
+		None
+	}
+
+	fn take(&mut self, frame: u64, count: usize) -> u64 {
+		self.mark_range(frame, count as u64, false);
+		self.next = frame as usize + count;
+		frame * PAGE_SIZE
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use core::prelude::*;
+	use collections::Vec;
+
+	use table::{MemoryMap, PAGE_SIZE};
+	use super::FrameAllocator;
+
+	// frames 0-15 conventional, 16-19 reserved, 20-27 loader data, 28-63 missing, 64-127 boot services code
+	const MAP: [u64; 4 * 5] = [
+		7, 0x00000, 0, 16, 0xF,
+		0, 0x10000, 0, 4, 0xF,
+		2, 0x14000, 0, 8, 0xF,
+		3, 0x40000, 0, 64, 0xF
+	];
+
+	// the host addresses of the bitmap and the map buffer are far above the frames the map covers,
+	// so reserving them doesn't disturb the counts below
+	#[test]
+	fn reserves_handoff_regions_and_frame_zero() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 40);
+		let mut bitmap = [0; 2];
+		assert_eq!(FrameAllocator::bitmap_words(&map), 2);
+		let mut allocator = FrameAllocator::new(&map, &mut bitmap, &[(0x14000, 0x2000)]);
+		assert_eq!(allocator.free_frames(), 16 + 8 + 64 - 1 - 2);
+
+		let mut frames = Vec::new();
+		while let Some(address) = allocator.alloc() {
+			frames.push(address / PAGE_SIZE);
+		}
+		assert_eq!(frames.len(), 85);
+		assert!(frames.iter().all(|frame| *frame != 0 && !(16..22).any(|reserved| reserved == *frame)));
+		assert_eq!(allocator.free_frames(), 0);
+	}
+
+	#[test]
+	fn contiguous_runs_skip_holes() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 40);
+		let mut bitmap = [0; 2];
+		let mut allocator = FrameAllocator::new(&map, &mut bitmap, &[]);
+		assert_eq!(allocator.alloc_contiguous(20), Some(0x40000));
+		assert_eq!(allocator.alloc_contiguous(44), Some(0x54000));
+		// 1-15 and 20-27 are left, neither is long enough
+		assert_eq!(allocator.alloc_contiguous(16), None);
+		assert_eq!(allocator.alloc_contiguous(8), Some(0x1000));
+		assert_eq!(allocator.alloc_contiguous(0), None);
+	}
+
+	#[test]
+	fn alloc_below_honours_the_limit() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 40);
+		let mut bitmap = [0; 2];
+		let mut allocator = FrameAllocator::new(&map, &mut bitmap, &[]);
+		assert_eq!(allocator.alloc_below(8, 0x40000), Some(0x1000));
+		// below frame 32 only 9-15 and 20-27 are free
+		assert_eq!(allocator.alloc_below(9, 0x20000), None);
+		assert_eq!(allocator.alloc_below(8, 0x20000), Some(0x14000));
+		assert_eq!(allocator.alloc_below(7, 0x10000), Some(0x9000));
+		assert_eq!(allocator.alloc_below(1, 0x20000), None);
+		assert_eq!(allocator.alloc_below_4g(1), Some(0x40000));
+	}
+
+	#[test]
+	fn mark_range_keeps_the_count_exact() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 40);
+		let mut bitmap = [0; 2];
+		let mut allocator = FrameAllocator::new(&map, &mut bitmap, &[]);
+		let free = allocator.free_frames();
+		// freeing free frames and reserving reserved ones changes nothing
+		allocator.free(0x1000, 4);
+		allocator.reserve(0x10000, 0x4000);
+		assert_eq!(allocator.free_frames(), free);
+		// ranges past the end of the bitmap are clipped
+		allocator.free(0x7F000, 8);
+		assert_eq!(allocator.free_frames(), free);
+		// a partial page still reserves the whole frame
+		allocator.reserve(0x40800, 1);
+		assert_eq!(allocator.free_frames(), free - 1);
+	}
+
+	#[test]
+	fn find_run_skips_full_words() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 40);
+		let mut bitmap = [0; 2];
+		let mut allocator = FrameAllocator::new(&map, &mut bitmap, &[(0, 0x40000)]);
+		assert_eq!(allocator.free_frames(), 64);
+		assert_eq!(allocator.alloc(), Some(0x40000));
+		allocator.free(0x40000, 1);
+		assert_eq!(allocator.alloc_contiguous(64), Some(0x40000));
+		assert_eq!(allocator.alloc(), None);
+	}
+}
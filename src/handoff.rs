@@ -0,0 +1,369 @@
+use core::prelude::*;
+use core::slice;
+
+use table::{MemoryMap, MemoryType};
+use Status;
+
+// what a kernel needs to know about a range once boot services are gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryClass {
+	Usable,
+	Reserved,
+	AcpiReclaimable,
+	AcpiNvs,
+	Unusable,
+	Persistent
+}
+
+// follows what Linux does when it builds its e820 table from the UEFI map
+pub fn classify(typ: MemoryType) -> MemoryClass {
+	match typ {
+		MemoryType::LoaderCode | MemoryType::LoaderData
+			| MemoryType::BootServicesCode | MemoryType::BootServicesData
+			| MemoryType::Conventional => MemoryClass::Usable,
+		MemoryType::AcpiReclaimable => MemoryClass::AcpiReclaimable,
+		MemoryType::AcpiMemoryNvs => MemoryClass::AcpiNvs,
+		MemoryType::Unusable => MemoryClass::Unusable,
+		MemoryType::PersistentMemory => MemoryClass::Persistent,
+		// unaccepted memory has to be accepted before first use, which legacy kernels don't know how to do
+		_ => MemoryClass::Reserved
+	}
+}
+
+pub const E820_RAM: u32 = 1;
+pub const E820_RESERVED: u32 = 2;
+pub const E820_ACPI: u32 = 3;
+pub const E820_NVS: u32 = 4;
+pub const E820_UNUSABLE: u32 = 5;
+pub const E820_PMEM: u32 = 7;
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct E820Entry {
+	pub addr: u64,
+	pub size: u64,
+	pub typ: u32
+}
+
+fn e820_type(class: MemoryClass) -> u32 {
+	match class {
+		MemoryClass::Usable => E820_RAM,
+		MemoryClass::Reserved => E820_RESERVED,
+		MemoryClass::AcpiReclaimable => E820_ACPI,
+		MemoryClass::AcpiNvs => E820_NVS,
+		MemoryClass::Unusable => E820_UNUSABLE,
+		MemoryClass::Persistent => E820_PMEM
+	}
+}
+
+// returns the number of entries written, sorted and with adjacent ranges of the same type merged
+pub fn to_e820(map: &MemoryMap, entries: &mut [E820Entry]) -> ::Result<usize> {
+	convert(map, entries, e820_type)
+}
+
+pub const MULTIBOOT2_TAG_MMAP: u32 = 6;
+
+pub const MULTIBOOT2_MEMORY_AVAILABLE: u32 = 1;
+pub const MULTIBOOT2_MEMORY_RESERVED: u32 = 2;
+pub const MULTIBOOT2_MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+pub const MULTIBOOT2_MEMORY_NVS: u32 = 4;
+pub const MULTIBOOT2_MEMORY_BADRAM: u32 = 5;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Multiboot2MmapTag {
+	pub typ: u32,
+	pub size: u32,
+	pub entry_size: u32,
+	pub entry_version: u32
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Multiboot2MmapEntry {
+	pub base_addr: u64,
+	pub length: u64,
+	pub typ: u32,
+	pub reserved: u32
+}
+
+fn multiboot2_type(class: MemoryClass) -> u32 {
+	match class {
+		MemoryClass::Usable => MULTIBOOT2_MEMORY_AVAILABLE,
+		MemoryClass::AcpiReclaimable => MULTIBOOT2_MEMORY_ACPI_RECLAIMABLE,
+		MemoryClass::AcpiNvs => MULTIBOOT2_MEMORY_NVS,
+		MemoryClass::Unusable => MULTIBOOT2_MEMORY_BADRAM,
+		// multiboot2 has no type for persistent memory
+		MemoryClass::Reserved | MemoryClass::Persistent => MULTIBOOT2_MEMORY_RESERVED
+	}
+}
+
+// writes a complete mmap tag into buffer (taking u64s keeps it 8-byte aligned as the spec requires)
+// and returns the tag size in bytes
+pub fn to_multiboot2(map: &MemoryMap, buffer: &mut [u64]) -> ::Result<usize> {
+	if buffer.len() < 2 {
+		return Err(Status::BufferTooSmall);
+	}
+	let count = {
+		let entries = unsafe {
+			slice::from_raw_parts_mut(buffer[2..].as_mut_ptr() as *mut Multiboot2MmapEntry, (buffer.len() - 2) / 3)
+		};
+		try!(convert(map, entries, multiboot2_type))
+	};
+	let size = 16 + count * 24;
+	unsafe {
+		*(buffer.as_mut_ptr() as *mut Multiboot2MmapTag) = Multiboot2MmapTag {
+			typ: MULTIBOOT2_TAG_MMAP,
+			size: size as u32,
+			entry_size: 24,
+			entry_version: 0
+		};
+	}
+	Ok(size)
+}
+
+trait Entry: Copy {
+	fn new(base: u64, length: u64, typ: u32) -> Self;
+	fn base(&self) -> u64;
+	fn length(&self) -> u64;
+	fn typ(&self) -> u32;
+
+	fn end(&self) -> u64 {
+		self.base() + self.length()
+	}
+}
+
+impl Entry for E820Entry {
+	fn new(base: u64, length: u64, typ: u32) -> E820Entry {
+		E820Entry {
+			addr: base,
+			size: length,
+			typ: typ
+		}
+	}
+
+	fn base(&self) -> u64 {
+		self.addr
+	}
+
+	fn length(&self) -> u64 {
+		self.size
+	}
+
+	fn typ(&self) -> u32 {
+		self.typ
+	}
+}
+
+impl Entry for Multiboot2MmapEntry {
+	fn new(base: u64, length: u64, typ: u32) -> Multiboot2MmapEntry {
+		Multiboot2MmapEntry {
+			base_addr: base,
+			length: length,
+			typ: typ,
+			reserved: 0
+		}
+	}
+
+	fn base(&self) -> u64 {
+		self.base_addr
+	}
+
+	fn length(&self) -> u64 {
+		self.length
+	}
+
+	fn typ(&self) -> u32 {
+		self.typ
+	}
+}
+
+fn convert<T: Entry>(map: &MemoryMap, entries: &mut [T], type_of: fn(MemoryClass) -> u32) -> ::Result<usize> {
+	let mut count = 0;
+	for descriptor in map.iter() {
+		let typ = type_of(classify(descriptor.memory_type()));
+		count = try!(insert(entries, count, <T as Entry>::new(descriptor.phys, descriptor.size(), typ)));
+	}
+	Ok(count)
+}
+
+// keeps entries[..count] sorted and merged, so the buffer only has to fit the final result
+fn insert<T: Entry>(entries: &mut [T], count: usize, entry: T) -> ::Result<usize> {
+	let mut index = count;
+	while index > 0 && entries[index - 1].base() > entry.base() {
+		index -= 1;
+	}
+
+	if index > 0 && entries[index - 1].typ() == entry.typ() && entries[index - 1].end() == entry.base() {
+		let previous = entries[index - 1];
+		let mut merged = <T as Entry>::new(previous.base(), previous.length() + entry.length(), entry.typ());
+		let mut count = count;
+		// the new range may also close the gap to the following entry
+		if index < count && entries[index].typ() == merged.typ() && merged.end() == entries[index].base() {
+			merged = <T as Entry>::new(merged.base(), merged.length() + entries[index].length(), merged.typ());
+			for i in index..count - 1 {
+				entries[i] = entries[i + 1];
+			}
+			count -= 1;
+		}
+		entries[index - 1] = merged;
+		return Ok(count);
+	}
+
+	if index < count && entries[index].typ() == entry.typ() && entry.end() == entries[index].base() {
+		entries[index] = <T as Entry>::new(entry.base(), entry.length() + entries[index].length(), entry.typ());
+		return Ok(count);
+	}
+
+	if count == entries.len() {
+		return Err(Status::BufferTooSmall);
+	}
+	let mut i = count;
+	while i > index {
+		entries[i] = entries[i - 1];
+		i -= 1;
+	}
+	entries[index] = entry;
+	Ok(count + 1)
+}
+
+#[allow(dead_code)]
+fn assert_layout() {
+	assert_size!(E820Entry, 20, 20);
+	assert_size!(Multiboot2MmapTag, 16, 16);
+	assert_size!(Multiboot2MmapEntry, 24, 24);
+}
+
+#[cfg(test)]
+mod tests {
+	use core::prelude::*;
+	use collections::Vec;
+
+	use table::{MemoryMap, MemoryType};
+	use Status;
+	use super::*;
+
+	// synthetic, laid out the way OVMF reports its map: a 48 byte stride (type, physical start,
+	// virtual start, pages, attributes and 8 bytes of padding per descriptor) and descriptors not
+	// sorted by address
+	const MAP: [u64; 12 * 6] = [
+		7, 0x00000000, 0, 0x9F, 0xF, 0, // conventional
+		0, 0x0009F000, 0, 0x1, 0xF, 0, // reserved
+		10, 0x00800000, 0, 0x8, 0xF, 0, // acpi nvs
+		4, 0x00100000, 0, 0x700, 0xF, 0, // boot services data
+		1, 0x00900000, 0, 0x10, 0xF, 0, // loader code
+		7, 0x00920000, 0, 0x60, 0xF, 0, // conventional
+		9, 0x00820000, 0, 0x2, 0xF, 0, // acpi reclaim
+		6, 0x00822000, 0, 0x4, 0x800000000000000F, 0, // runtime services data
+		3, 0x00910000, 0, 0x10, 0xF, 0, // boot services code, closes the gap between the two above
+		7, 0x00808000, 0, 0x8, 0xF, 0, // conventional
+		2, 0x00810000, 0, 0x10, 0xF, 0, // loader data
+		11, 0xFFC00000, 0, 0x400, 0x8000000000000001, 0 // mmio (flash)
+	];
+
+	const MAP_E820: [(u64, u64, u32); 9] = [
+		(0x00000000, 0x9F000, E820_RAM),
+		(0x0009F000, 0x1000, E820_RESERVED),
+		(0x00100000, 0x700000, E820_RAM),
+		(0x00800000, 0x8000, E820_NVS),
+		(0x00808000, 0x18000, E820_RAM),
+		(0x00820000, 0x2000, E820_ACPI),
+		(0x00822000, 0x4000, E820_RESERVED),
+		(0x00900000, 0x80000, E820_RAM),
+		(0xFFC00000, 0x400000, E820_RESERVED)
+	];
+
+	fn e820_empty() -> E820Entry {
+		E820Entry {
+			addr: 0,
+			size: 0,
+			typ: 0
+		}
+	}
+
+	#[test]
+	fn classify_types() {
+		assert_eq!(classify(MemoryType::BootServicesData), MemoryClass::Usable);
+		assert_eq!(classify(MemoryType::LoaderCode), MemoryClass::Usable);
+		assert_eq!(classify(MemoryType::RuntimeServicesCode), MemoryClass::Reserved);
+		assert_eq!(classify(MemoryType::AcpiMemoryNvs), MemoryClass::AcpiNvs);
+		assert_eq!(classify(MemoryType::PersistentMemory), MemoryClass::Persistent);
+		assert_eq!(classify(MemoryType::Unaccepted), MemoryClass::Reserved);
+		assert_eq!(classify(MemoryType::Os(0x80000001)), MemoryClass::Reserved);
+	}
+
+	#[test]
+	fn e820_from_map() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 48);
+		let mut entries = [e820_empty(); 16];
+		let count = to_e820(&map, &mut entries).unwrap();
+		let entries: Vec<(u64, u64, u32)> = entries[..count].iter().map(|entry| (entry.addr, entry.size, entry.typ)).collect();
+		assert_eq!(entries, MAP_E820.to_vec());
+	}
+
+	#[test]
+	fn e820_exact_fit() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 48);
+		let mut entries = [e820_empty(); 9];
+		assert_eq!(to_e820(&map, &mut entries), Ok(9));
+	}
+
+	#[test]
+	fn e820_buffer_too_small() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 48);
+		let mut entries = [e820_empty(); 8];
+		assert_eq!(to_e820(&map, &mut entries), Err(Status::BufferTooSmall));
+	}
+
+	#[test]
+	fn e820_merges_both_neighbours() {
+		// the middle range arrives last and joins the one below and the one above into a single entry
+		let mut blob = [
+			7, 0x1000, 0, 0x1, 0xF, 0,
+			7, 0x3000, 0, 0x1, 0xF, 0,
+			0, 0x5000, 0, 0x1, 0xF, 0,
+			4, 0x2000, 0, 0x1, 0xF, 0
+		];
+		let map = MemoryMap::from_blob(&mut blob, 48);
+		let mut entries = [e820_empty(); 4];
+		assert_eq!(to_e820(&map, &mut entries), Ok(2));
+		assert_eq!((entries[0].addr, entries[0].size, entries[0].typ), (0x1000, 0x3000, E820_RAM));
+		assert_eq!((entries[1].addr, entries[1].size, entries[1].typ), (0x5000, 0x1000, E820_RESERVED));
+	}
+
+	#[test]
+	fn multiboot2_from_map() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 48);
+		let mut buffer = [0xAAAAAAAAAAAAAAAA; 2 + 9 * 3];
+		assert_eq!(to_multiboot2(&map, &mut buffer), Ok(16 + 9 * 24));
+		// the tag as little endian words: type 6 and size 232, entry size 24 and version 0, then the
+		// entries as base, length, type and reserved
+		let expected: [u64; 2 + 9 * 3] = [
+			6 | 232 << 32, 24,
+			0x00000000, 0x9F000, 1,
+			0x0009F000, 0x1000, 2,
+			0x00100000, 0x700000, 1,
+			0x00800000, 0x8000, 4,
+			0x00808000, 0x18000, 1,
+			0x00820000, 0x2000, 3,
+			0x00822000, 0x4000, 2,
+			0x00900000, 0x80000, 1,
+			0xFFC00000, 0x400000, 2
+		];
+		assert_eq!(buffer.to_vec(), expected.to_vec());
+	}
+
+	#[test]
+	fn multiboot2_buffer_too_small() {
+		let mut blob = MAP;
+		let map = MemoryMap::from_blob(&mut blob, 48);
+		let mut header_only = [0; 1];
+		assert_eq!(to_multiboot2(&map, &mut header_only), Err(Status::BufferTooSmall));
+		let mut short = [0; 2 + 8 * 3];
+		assert_eq!(to_multiboot2(&map, &mut short), Err(Status::BufferTooSmall));
+	}
+}
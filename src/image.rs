@@ -0,0 +1,156 @@
+use core::prelude::*;
+use core::mem;
+use core::ptr;
+use core::slice;
+use collections::{Vec, String};
+
+use protocol::{DevicePath, LoadedImage};
+use table::MemoryType;
+use ::{Status, Handle};
+
+// an image that has been loaded but not started yet, dropping it unloads it again
+pub struct Image {
+	handle: Handle,
+	loaded: bool,
+	load_options: Vec<u8>
+}
+
+impl Image {
+	// boot_policy is set when the path comes from the boot manager rather than a file the user picked
+	pub fn load_from_path(path: &DevicePath, boot_policy: bool) -> ::Result<Image> {
+		Image::load(boot_policy, path, ptr::null(), 0)
+	}
+
+	// path is optional here, it just ends up in the image's LoadedImage
+	pub fn load_from_buffer(buffer: &[u8], path: Option<&DevicePath>) -> ::Result<Image> {
+		let path = path.map(|path| path as *const DevicePath).unwrap_or(ptr::null());
+		Image::load(false, path, buffer.as_ptr(), buffer.len())
+	}
+
+	fn load(boot_policy: bool, path: *const DevicePath, source: *const u8, source_size: usize) -> ::Result<Image> {
+		let boot_services = ::get_boot_services();
+		let (status, handle) = unsafe { boot_services.load_image(boot_policy, ::get_current_image(), path, source, source_size) };
+		if status.is_error() {
+			// an image that failed verification still got loaded, and nobody else is going to unload it
+			if status == Status::SecurityViolation && !handle.is_null() {
+				unsafe {
+					boot_services.unload_image(handle);
+				}
+			}
+			return Err(status);
+		}
+		Ok(Image::from_handle(handle))
+	}
+
+	fn from_handle(handle: Handle) -> Image {
+		Image {
+			handle: handle,
+			loaded: true,
+			load_options: Vec::new()
+		}
+	}
+
+	pub fn handle(&self) -> Handle {
+		self.handle
+	}
+
+	pub fn set_load_options(&mut self, options: &[u8]) -> ::Result<()> {
+		self.load_options = options.to_vec();
+		let loaded_image = try!(self.handle.get_protocol::<LoadedImage>());
+		unsafe {
+			(*loaded_image.as_mut_ptr()).set_load_options(self.load_options.as_ptr() as *const (), self.load_options.len() as u32);
+		}
+		Ok(())
+	}
+
+	// the usual way to pass arguments, as a UCS-2 string like the shell does
+	pub fn set_command_line(&mut self, command_line: &str) -> ::Result<()> {
+		let command_line = ::to_utf16(command_line);
+		let bytes = unsafe { slice::from_raw_parts(command_line.as_ptr() as *const u8, command_line.len() * 2) };
+		self.set_load_options(bytes)
+	}
+
+	// runs the image until it exits, the firmware unloads it afterwards unless it's a driver that stayed resident
+	pub fn start(mut self) -> ExitData {
+		self.loaded = false;
+		let (status, size, data) = unsafe { ::get_boot_services().start_image(self.handle) };
+		let exit_data = if data.is_null() {
+			Vec::new()
+		} else {
+			let exit_data = unsafe { slice::from_raw_parts(data, size / 2).to_vec() };
+			unsafe { ::get_boot_services().free(data as *mut ()); }
+			exit_data
+		};
+		// applications are unloaded by now, but a driver that stayed resident may hold on to its load options
+		if !status.is_error() && self.handle.supports::<LoadedImage>() {
+			mem::forget(mem::replace(&mut self.load_options, Vec::new()));
+		}
+		ExitData {
+			status: status,
+			data: exit_data
+		}
+	}
+
+	pub fn unload(mut self) -> ::Result<()> {
+		self.loaded = false;
+		unsafe { ::get_boot_services().unload_image(self.handle) }.into_result()
+	}
+}
+
+impl Drop for Image {
+	fn drop(&mut self) {
+		if self.loaded && ::boot_services_active() {
+			unsafe {
+				::get_boot_services().unload_image(self.handle);
+			}
+		}
+	}
+}
+
+// what a started image handed back through Exit
+pub struct ExitData {
+	pub status: Status,
+	data: Vec<u16>
+}
+
+impl ExitData {
+	// exit data starts with a null terminated string
+	pub fn message(&self) -> String {
+		let length = self.data.iter().position(|c| *c == 0).unwrap_or(self.data.len());
+		String::from_utf16_lossy(&self.data[..length])
+	}
+
+	// anything the image put after the string
+	pub fn extra_data(&self) -> &[u8] {
+		let start = self.data.iter().position(|c| *c == 0).map(|i| i + 1).unwrap_or(self.data.len());
+		let extra = &self.data[start..];
+		unsafe {
+			slice::from_raw_parts(extra.as_ptr() as *const u8, extra.len() * 2)
+		}
+	}
+}
+
+// runs the shutdown hooks and exits the current image, message is only passed on for error statuses
+pub fn exit(status: Status, message: Option<&str>) -> ! {
+	::shutdown::run_hooks();
+	let boot_services = ::get_boot_services();
+	let (size, data) = match message {
+		Some(message) if status.is_error() => {
+			let message = ::to_utf16(message);
+			let size = message.len() * 2;
+			match boot_services.alloc(MemoryType::LoaderData, size) {
+				Some(data) => {
+					unsafe { ptr::copy(message.as_ptr(), data as *mut u16, message.len()); }
+					(size, data as *mut u16)
+				},
+				None => (0, ptr::null_mut())
+			}
+		},
+		_ => (0, ptr::null_mut())
+	};
+	unsafe {
+		boot_services.exit(::get_current_image(), status, size, data);
+	}
+	// exit only comes back if our image handle is bad, nothing sensible left to do
+	loop { }
+}
@@ -0,0 +1,276 @@
+use core::prelude::*;
+use core::mem;
+use alloc::boxed::Box;
+use collections::Vec;
+
+use protocol::Protocol;
+use event::{Event, EventType, EVT_NOTIFY_SIGNAL};
+use executor::{Future, Poll, Context};
+use table::Tpl;
+use ::{Status, Guid, Handle};
+
+// a protocol interface we own that is installed in the handle database, the box keeps it at a
+// fixed address for as long as the firmware can see it, and dropping this uninstalls it again
+pub struct Installed<T: Protocol + 'static> {
+	handle: Handle,
+	interface: Option<Box<T>>
+}
+
+impl<T: Protocol + 'static> Installed<T> {
+	// handle None creates a new handle
+	pub fn install(handle: Option<Handle>, interface: T) -> ::Result<Installed<T>> {
+		let mut interface = Box::new(interface);
+		let mut handle = handle.unwrap_or(Handle::null());
+		try!(unsafe { ::get_boot_services().install_protocol_interface(&mut handle, &<T as Protocol>::guid(), &mut *interface as *mut T as *mut ()) }.into_result());
+		Ok(Installed {
+			handle: handle,
+			interface: Some(interface)
+		})
+	}
+
+	pub fn handle(&self) -> Handle {
+		self.handle
+	}
+
+	pub fn interface(&self) -> &T {
+		self.interface.as_ref().unwrap()
+	}
+
+	// the firmware reads the interface whenever it likes, so changes should be complete before
+	// anything can call into it
+	pub fn interface_mut(&mut self) -> &mut T {
+		self.interface.as_mut().unwrap()
+	}
+
+	// swaps in a new interface (consumers get disconnected and reconnected by the firmware) and
+	// hands back the old one
+	pub fn reinstall(&mut self, interface: T) -> ::Result<T> {
+		let mut new = Box::new(interface);
+		{
+			let old = self.interface.as_mut().unwrap();
+			try!(unsafe { ::get_boot_services().reinstall_protocol_interface(self.handle, &<T as Protocol>::guid(), &mut **old as *mut T as *mut (), &mut *new as *mut T as *mut ()) }.into_result());
+		}
+		let old = mem::replace(&mut self.interface, Some(new));
+		Ok(*old.unwrap())
+	}
+
+	// fails with AccessDenied if a driver has the protocol open and refuses to let go
+	pub fn uninstall(mut self) -> Result<T, (Installed<T>, Status)> {
+		match self.remove() {
+			Ok(()) => Ok(*self.interface.take().unwrap()),
+			Err(error) => Err((self, error))
+		}
+	}
+
+	// leaves the interface installed for good, for drivers that stay resident
+	pub fn leak(mut self) -> Handle {
+		mem::forget(self.interface.take().unwrap());
+		self.handle
+	}
+
+	fn remove(&mut self) -> ::Result<()> {
+		let interface = self.interface.as_mut().unwrap();
+		unsafe { ::get_boot_services().uninstall_protocol_interface(self.handle, &<T as Protocol>::guid(), &mut **interface as *mut T as *mut ()) }.into_result()
+	}
+}
+
+impl<T: Protocol + 'static> Drop for Installed<T> {
+	fn drop(&mut self) {
+		if self.interface.is_none() || !::boot_services_active() {
+			return;
+		}
+		if self.remove().is_err() {
+			// the firmware still points at it, so freeing it would be worse than leaking it
+			mem::forget(self.interface.take().unwrap());
+		}
+	}
+}
+
+trait Interface {
+	fn guid(&self) -> Guid;
+	fn as_mut_ptr(&mut self) -> *mut ();
+}
+
+impl<T: Protocol> Interface for T {
+	fn guid(&self) -> Guid {
+		<T as Protocol>::guid()
+	}
+
+	fn as_mut_ptr(&mut self) -> *mut () {
+		self as *mut T as *mut ()
+	}
+}
+
+// several interfaces installed on one handle as a unit, like InstallMultipleProtocolInterfaces
+pub struct ProtocolGroup {
+	interfaces: Vec<Box<Interface + 'static>>
+}
+
+impl ProtocolGroup {
+	pub fn new() -> ProtocolGroup {
+		ProtocolGroup {
+			interfaces: Vec::new()
+		}
+	}
+
+	pub fn with<T: Protocol + 'static>(mut self, interface: T) -> ProtocolGroup {
+		self.interfaces.push(Box::new(interface));
+		self
+	}
+
+	// either everything gets installed or nothing does
+	pub fn install(mut self, handle: Option<Handle>) -> ::Result<InstalledGroup> {
+		let mut handle = handle.unwrap_or(Handle::null());
+		for i in 0..self.interfaces.len() {
+			let status = {
+				let interface = &mut self.interfaces[i];
+				unsafe { ::get_boot_services().install_protocol_interface(&mut handle, &interface.guid(), interface.as_mut_ptr()) }
+			};
+			if status.is_error() {
+				let mut installed = InstalledGroup {
+					handle: handle,
+					interfaces: self.interfaces
+				};
+				installed.interfaces.truncate(i);
+				// whatever can't be rolled back gets leaked by remove() rather than freed under the firmware
+				let _ = installed.remove();
+				return Err(status);
+			}
+		}
+		Ok(InstalledGroup {
+			handle: handle,
+			interfaces: self.interfaces
+		})
+	}
+}
+
+pub struct InstalledGroup {
+	handle: Handle,
+	interfaces: Vec<Box<Interface + 'static>>
+}
+
+impl InstalledGroup {
+	pub fn handle(&self) -> Handle {
+		self.handle
+	}
+
+	pub fn uninstall(mut self) -> ::Result<()> {
+		self.remove()
+	}
+
+	pub fn leak(mut self) -> Handle {
+		mem::forget(mem::replace(&mut self.interfaces, Vec::new()));
+		self.handle
+	}
+
+	// uninstalls in reverse order, anything that can't be uninstalled is leaked and the first error returned
+	fn remove(&mut self) -> ::Result<()> {
+		let mut result = Ok(());
+		while let Some(mut interface) = self.interfaces.pop() {
+			let status = unsafe { ::get_boot_services().uninstall_protocol_interface(self.handle, &interface.guid(), interface.as_mut_ptr()) };
+			if status.is_error() {
+				mem::forget(interface);
+				if result.is_ok() {
+					result = Err(status);
+				}
+			}
+		}
+		result
+	}
+}
+
+impl Drop for InstalledGroup {
+	fn drop(&mut self) {
+		if ::boot_services_active() {
+			let _ = self.remove();
+		} else {
+			mem::forget(mem::replace(&mut self.interfaces, Vec::new()));
+		}
+	}
+}
+
+// watches for installations of a protocol, the event is signalled every time one happens
+pub struct ProtocolNotify {
+	event: Event,
+	registration: *const ()
+}
+
+impl ProtocolNotify {
+	pub fn new<T: Protocol>() -> ::Result<ProtocolNotify> {
+		ProtocolNotify::for_guid(<T as Protocol>::guid())
+	}
+
+	pub fn for_guid(guid: Guid) -> ::Result<ProtocolNotify> {
+		ProtocolNotify::register(guid, try!(Event::new(EventType::empty())))
+	}
+
+	// notify gets called at tpl on each installation, use next_handle() in it to see where
+	pub fn with_notify<F: FnMut() + 'static>(guid: Guid, tpl: Tpl, notify: F) -> ::Result<ProtocolNotify> {
+		ProtocolNotify::register(guid, try!(Event::with_notify(EVT_NOTIFY_SIGNAL, tpl, notify)))
+	}
+
+	fn register(guid: Guid, event: Event) -> ::Result<ProtocolNotify> {
+		let registration = try!(unsafe { ::get_boot_services().register_protocol_notify(&guid, event.as_raw()) });
+		Ok(ProtocolNotify {
+			event: event,
+			registration: registration
+		})
+	}
+
+	pub fn event(&self) -> &Event {
+		&self.event
+	}
+
+	pub fn next_handle(&self) -> Option<Handle> {
+		unsafe { ::get_boot_services().next_notified_handle(self.registration) }
+	}
+
+	pub fn handles<'a>(&'a self) -> NotifiedHandles<'a> {
+		NotifiedHandles {
+			notify: self
+		}
+	}
+
+	// resolves to the next newly installed handle, usable as a stream by awaiting it repeatedly
+	// (not for ones made with_notify, signal events can't be waited on)
+	pub fn next<'a>(&'a self) -> NextHandle<'a> {
+		NextHandle {
+			notify: self
+		}
+	}
+}
+
+// closing the event is what unregisters the notification, so dropping the ProtocolNotify is enough
+
+pub struct NotifiedHandles<'a> {
+	notify: &'a ProtocolNotify
+}
+
+impl<'a> Iterator for NotifiedHandles<'a> {
+	type Item = Handle;
+
+	fn next(&mut self) -> Option<Handle> {
+		self.notify.next_handle()
+	}
+}
+
+pub struct NextHandle<'a> {
+	notify: &'a ProtocolNotify
+}
+
+impl<'a> Future for NextHandle<'a> {
+	type Output = Handle;
+
+	fn poll(&mut self, cx: &mut Context) -> Poll<Handle> {
+		// the executor re-signals the event that woke it, so clear it before looking, otherwise the
+		// next wait returns straight away and the executor spins
+		let _ = self.notify.event.check();
+		match self.notify.next_handle() {
+			Some(handle) => Poll::Ready(handle),
+			None => {
+				cx.wait_on(&self.notify.event);
+				Poll::Pending
+			}
+		}
+	}
+}
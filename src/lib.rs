@@ -1,23 +1,130 @@
 #![no_std]
-#![feature(lang_items, no_std, type_macros)]
-#![feature(core, collections, libc, unicode, core_prelude)]
+#![feature(lang_items, no_std, type_macros, associated_consts)]
+#![feature(core, collections, alloc, libc, unicode, core_prelude)]
 
 extern crate libc;
+extern crate alloc;
 #[macro_use]
 extern crate collections;
 extern crate coreio as io;
+// the tests run on the host, where the lang items, allocator and entry point come from std
+#[cfg(test)]
+#[macro_use]
+extern crate std;
 
 use core::ops::{Deref, DerefMut};
 use core::fmt;
 
 #[cfg(target_pointer_width="32")]
 macro_rules! efi_fn {
-	($($typ:ty),*) => (extern "system" fn($($typ),*) -> $crate::Status)
+	(($($typ:ty),*) -> $ret:ty) => (extern "system" fn($($typ),*) -> $ret);
+	($($typ:ty),*) => (extern "system" fn($($typ),*) -> usize)
 }
 
 #[cfg(target_pointer_width="64")]
 macro_rules! efi_fn {
-	($($typ:ty),*) => (extern "win64" fn($($typ),*) -> $crate::Status)
+	(($($typ:ty),*) -> $ret:ty) => (extern "win64" fn($($typ),*) -> $ret);
+	($($typ:ty),*) => (extern "win64" fn($($typ),*) -> usize)
+}
+
+// defines a function the firmware can call back into
+#[cfg(target_pointer_width="32")]
+macro_rules! efi_callback {
+	(fn $name:ident($($arg:ident: $typ:ty),*) $(-> $ret:ty)* $body:block) => (extern "system" fn $name($($arg: $typ),*) $(-> $ret)* $body)
+}
+
+#[cfg(target_pointer_width="64")]
+macro_rules! efi_callback {
+	(fn $name:ident($($arg:ident: $typ:ty),*) $(-> $ret:ty)* $body:block) => (extern "win64" fn $name($($arg: $typ),*) $(-> $ret)* $body)
+}
+
+macro_rules! efi_flags {
+	($name:ident: $typ:ty { $($flag:ident = $value:expr),* $(,)* }) => {
+		#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+		pub struct $name($typ);
+
+		$(pub const $flag: $name = $name($value);)*
+
+		impl $name {
+			pub fn empty() -> $name {
+				$name(0)
+			}
+
+			pub fn from_bits(bits: $typ) -> $name {
+				$name(bits)
+			}
+
+			pub fn bits(&self) -> $typ {
+				self.0
+			}
+
+			pub fn is_empty(&self) -> bool {
+				self.0 == 0
+			}
+
+			pub fn contains(&self, other: $name) -> bool {
+				self.0 & other.0 == other.0
+			}
+		}
+
+		impl ::core::ops::BitOr for $name {
+			type Output = $name;
+
+			fn bitor(self, other: $name) -> $name {
+				$name(self.0 | other.0)
+			}
+		}
+
+		impl ::core::ops::BitAnd for $name {
+			type Output = $name;
+
+			fn bitand(self, other: $name) -> $name {
+				$name(self.0 & other.0)
+			}
+		}
+
+		impl ::core::ops::Not for $name {
+			type Output = $name;
+
+			fn not(self) -> $name {
+				$name(!self.0)
+			}
+		}
+	}
+}
+
+// checks the size of a firmware-facing type at compile time, transmute refuses to compile when
+// the sizes differ. on ia32 rust only aligns u64 to 4 bytes where the spec wants 8, which never moves
+// a field in our structs but can drop tail padding, so the 32-bit sizes are what rust lays out
+macro_rules! assert_size {
+	($typ:ty, $size32:expr, $size64:expr) => ({
+		#[cfg(target_pointer_width="32")]
+		#[allow(dead_code)]
+		unsafe fn check() {
+			let _: [u8; $size32] = ::core::mem::transmute(::core::mem::uninitialized::<$typ>());
+		}
+
+		#[cfg(target_pointer_width="64")]
+		#[allow(dead_code)]
+		unsafe fn check() {
+			let _: [u8; $size64] = ::core::mem::transmute(::core::mem::uninitialized::<$typ>());
+		}
+	})
+}
+
+// checks where a field of a firmware-facing type sits. offsets can't be computed in a constant, so
+// unlike assert_size this runs, from the layout tests. only the sizes are checked at compile time for
+// the target being built, the offsets only for the host cargo test runs on, so on a 64-bit host the
+// 32-bit offsets are written down but not checked
+#[cfg(test)]
+macro_rules! assert_offset {
+	($typ:ty, $field:ident, $offset32:expr, $offset64:expr) => ({
+		let value: $typ = unsafe { ::core::mem::uninitialized() };
+		let offset = &value.$field as *const _ as usize - &value as *const $typ as usize;
+		::core::mem::forget(value);
+		let expected: usize = if cfg!(target_pointer_width="32") { $offset32 } else { $offset64 };
+		assert!(offset == expected, "{}::{} is at {}, expected {}", stringify!($typ), stringify!($field), offset, expected);
+	})
 }
 
 #[macro_use]
@@ -28,18 +135,164 @@ pub mod table;
 pub mod protocol;
 pub mod panic;
 pub mod mem;
+pub mod handoff;
+pub mod frame;
+pub mod event;
+pub mod executor;
+pub mod tpl;
+pub mod interface;
+pub mod image;
+pub mod watchdog;
+pub mod driver;
+pub mod shutdown;
+pub mod variable;
+pub mod boot;
+pub mod os_indications;
+pub mod reset;
+pub mod runtime;
 
 pub use table::Table;
 
+#[cfg(not(test))]
 mod std { pub use core::*; }
 
-#[repr(usize)]
-#[derive(PartialEq, Eq)]
-pub enum Status {
-	Success = 0,
-	Aaaa = 1,
+#[cfg(target_pointer_width="32")]
+const ERROR_BIT: usize = 0x80000000;
+
+#[cfg(target_pointer_width="64")]
+const ERROR_BIT: usize = 0x8000000000000000;
+
+// firmware can return codes we don't know (oem ones, ones from newer specs), so this is a plain
+// number rather than an enum. it never crosses the firmware boundary itself, efi_fn! returns a raw
+// usize and callers wrap it, as a struct return isn't guaranteed to use the integer abi
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct Status(pub usize);
+
+#[allow(non_upper_case_globals)]
+impl Status {
+	pub const Success: Status = Status(0);
+
+	pub const WarnUnknownGlyph: Status = Status(1);
+	pub const WarnDeleteFailure: Status = Status(2);
+	pub const WarnWriteFailure: Status = Status(3);
+	pub const WarnBufferTooSmall: Status = Status(4);
+	pub const WarnStaleData: Status = Status(5);
+	pub const WarnFileSystem: Status = Status(6);
+	pub const WarnResetRequired: Status = Status(7);
+
+	pub const LoadError: Status = Status(ERROR_BIT | 1);
+	pub const InvalidParameter: Status = Status(ERROR_BIT | 2);
+	pub const Unsupported: Status = Status(ERROR_BIT | 3);
+	pub const BadBufferSize: Status = Status(ERROR_BIT | 4);
+	pub const BufferTooSmall: Status = Status(ERROR_BIT | 5);
+	pub const NotReady: Status = Status(ERROR_BIT | 6);
+	pub const DeviceError: Status = Status(ERROR_BIT | 7);
+	pub const WriteProtected: Status = Status(ERROR_BIT | 8);
+	pub const OutOfResources: Status = Status(ERROR_BIT | 9);
+	pub const VolumeCorrupted: Status = Status(ERROR_BIT | 10);
+	pub const VolumeFull: Status = Status(ERROR_BIT | 11);
+	pub const NoMedia: Status = Status(ERROR_BIT | 12);
+	pub const MediaChanged: Status = Status(ERROR_BIT | 13);
+	pub const NotFound: Status = Status(ERROR_BIT | 14);
+	pub const AccessDenied: Status = Status(ERROR_BIT | 15);
+	pub const NoResponse: Status = Status(ERROR_BIT | 16);
+	pub const NoMapping: Status = Status(ERROR_BIT | 17);
+	pub const Timeout: Status = Status(ERROR_BIT | 18);
+	pub const NotStarted: Status = Status(ERROR_BIT | 19);
+	pub const AlreadyStarted: Status = Status(ERROR_BIT | 20);
+	pub const Aborted: Status = Status(ERROR_BIT | 21);
+	pub const IcmpError: Status = Status(ERROR_BIT | 22);
+	pub const TftpError: Status = Status(ERROR_BIT | 23);
+	pub const ProtocolError: Status = Status(ERROR_BIT | 24);
+	pub const IncompatibleVersion: Status = Status(ERROR_BIT | 25);
+	pub const SecurityViolation: Status = Status(ERROR_BIT | 26);
+	pub const CrcError: Status = Status(ERROR_BIT | 27);
+	pub const EndOfMedia: Status = Status(ERROR_BIT | 28);
+	pub const EndOfFile: Status = Status(ERROR_BIT | 31);
+	pub const InvalidLanguage: Status = Status(ERROR_BIT | 32);
+	pub const CompromisedData: Status = Status(ERROR_BIT | 33);
+	pub const IpAddressConflict: Status = Status(ERROR_BIT | 34);
+	pub const HttpError: Status = Status(ERROR_BIT | 35);
+}
+
+impl Status {
+	pub fn is_error(&self) -> bool {
+		self.0 & ERROR_BIT != 0
+	}
+
+	pub fn is_warning(&self) -> bool {
+		*self != Status::Success && !self.is_error()
+	}
+
+	// warnings still mean the operation went through, so they count as success here
+	pub fn into_result(self) -> Result<()> {
+		if self.is_error() {
+			Err(self)
+		} else {
+			Ok(())
+		}
+	}
+
+	fn name(&self) -> Option<&'static str> {
+		let name = match *self {
+			Status::Success => "Success",
+			Status::WarnUnknownGlyph => "WarnUnknownGlyph",
+			Status::WarnDeleteFailure => "WarnDeleteFailure",
+			Status::WarnWriteFailure => "WarnWriteFailure",
+			Status::WarnBufferTooSmall => "WarnBufferTooSmall",
+			Status::WarnStaleData => "WarnStaleData",
+			Status::WarnFileSystem => "WarnFileSystem",
+			Status::WarnResetRequired => "WarnResetRequired",
+			Status::LoadError => "LoadError",
+			Status::InvalidParameter => "InvalidParameter",
+			Status::Unsupported => "Unsupported",
+			Status::BadBufferSize => "BadBufferSize",
+			Status::BufferTooSmall => "BufferTooSmall",
+			Status::NotReady => "NotReady",
+			Status::DeviceError => "DeviceError",
+			Status::WriteProtected => "WriteProtected",
+			Status::OutOfResources => "OutOfResources",
+			Status::VolumeCorrupted => "VolumeCorrupted",
+			Status::VolumeFull => "VolumeFull",
+			Status::NoMedia => "NoMedia",
+			Status::MediaChanged => "MediaChanged",
+			Status::NotFound => "NotFound",
+			Status::AccessDenied => "AccessDenied",
+			Status::NoResponse => "NoResponse",
+			Status::NoMapping => "NoMapping",
+			Status::Timeout => "Timeout",
+			Status::NotStarted => "NotStarted",
+			Status::AlreadyStarted => "AlreadyStarted",
+			Status::Aborted => "Aborted",
+			Status::IcmpError => "IcmpError",
+			Status::TftpError => "TftpError",
+			Status::ProtocolError => "ProtocolError",
+			Status::IncompatibleVersion => "IncompatibleVersion",
+			Status::SecurityViolation => "SecurityViolation",
+			Status::CrcError => "CrcError",
+			Status::EndOfMedia => "EndOfMedia",
+			Status::EndOfFile => "EndOfFile",
+			Status::InvalidLanguage => "InvalidLanguage",
+			Status::CompromisedData => "CompromisedData",
+			Status::IpAddressConflict => "IpAddressConflict",
+			Status::HttpError => "HttpError",
+			_ => return None
+		};
+		Some(name)
+	}
+}
+
+impl fmt::Debug for Status {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.name() {
+			Some(name) => f.write_str(name),
+			None => write!(f, "Status({:#x})", self.0)
+		}
+	}
 }
 
+pub type Result<T> = core::result::Result<T, Status>;
+
 pub static mut system_table: *const Table<table::System<'static>> = 0 as *const Table<table::System<'static>>;
 pub static mut boot_services: *const Table<table::BootServices> = 0 as *const Table<table::BootServices>;
 pub static mut runtime_services: *const Table<table::RuntimeServices> = 0 as *const Table<table::RuntimeServices>;
@@ -63,12 +316,34 @@ pub fn get_runtime_services() -> &'static Table<table::RuntimeServices> {
 	}
 }
 
+// boot_services gets cleared once exit_boot_services succeeds
+pub fn boot_services_active() -> bool {
+	unsafe {
+		!boot_services.is_null()
+	}
+}
+
 pub fn get_current_image() -> Handle {
 	unsafe {
 		current_image
 	}
 }
 
+// null terminated, which is what the firmware wants everywhere
+fn to_utf16(string: &str) -> collections::Vec<u16> {
+	let mut result = collections::Vec::with_capacity(string.len() + 1);
+	for c in string.chars() {
+		let mut buffer = [0; 2];
+		let length = c.encode_utf16(&mut buffer).unwrap();
+		for unit in buffer[..length].iter() {
+			result.push(*unit);
+		}
+	}
+	result.push(0);
+	result
+}
+
+#[repr(C)]
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub struct Guid(pub u32, pub u16, pub u16, pub u8, pub u8, pub u8, pub u8, pub u8, pub u8, pub u8, pub u8);
 
@@ -92,32 +367,106 @@ pub struct Handle {
 }
 
 impl Handle {
-	pub fn get_protocol<T: protocol::Protocol>(&self) -> Option<&T> {
-		let mut ptr: *const T = 0 as *const T;
-		unsafe {
-			get_boot_services().handle_protocol(*self, <T as protocol::Protocol>::guid(), &mut ptr as *mut *const T as *mut *const ()); // TODO: check the error
-		}
-		if ptr.is_null() {
-			None
+	pub fn null() -> Handle {
+		Handle { _ptr: 0 as *const () }
+	}
+
+	pub fn is_null(&self) -> bool {
+		self._ptr.is_null()
+	}
+
+	// opens the protocol for our own use, other agents can still open it too
+	pub fn get_protocol<T: protocol::Protocol>(&self) -> Result<protocol::ScopedProtocol<T>> {
+		protocol::ScopedProtocol::open(*self, get_current_image(), Handle::null(), protocol::OPEN_PROTOCOL_GET_PROTOCOL)
+	}
+
+	// drivers that have the protocol open BY_DRIVER get disconnected first, and nobody else can
+	// open it until the returned guard is dropped
+	pub fn open_protocol_exclusive<T: protocol::Protocol>(&self) -> Result<protocol::ScopedProtocol<T>> {
+		protocol::ScopedProtocol::open(*self, get_current_image(), Handle::null(), protocol::OPEN_PROTOCOL_EXCLUSIVE)
+	}
+
+	// for drivers, agent is the driver's image and self the controller it manages
+	pub fn open_protocol_by_driver<T: protocol::Protocol>(&self, agent: Handle, exclusive: bool) -> Result<protocol::ScopedProtocol<T>> {
+		let attributes = if exclusive {
+			protocol::OPEN_PROTOCOL_BY_DRIVER | protocol::OPEN_PROTOCOL_EXCLUSIVE
 		} else {
-			unsafe {
-				Some(&*ptr)
-			}
+			protocol::OPEN_PROTOCOL_BY_DRIVER
+		};
+		protocol::ScopedProtocol::open(*self, agent, *self, attributes)
+	}
+
+	// asks the firmware to bind drivers to this controller, recursive also connects the children they create
+	pub fn connect(&self, recursive: bool) -> Result<()> {
+		get_boot_services().connect_controller(*self, None, None, recursive).into_result()
+	}
+
+	// only tries the given drivers, in order
+	pub fn connect_drivers(&self, drivers: &[Handle], recursive: bool) -> Result<()> {
+		get_boot_services().connect_controller(*self, Some(drivers), None, recursive).into_result()
+	}
+
+	// driver None disconnects every driver managing this controller
+	pub fn disconnect(&self, driver: Option<Handle>) -> Result<()> {
+		get_boot_services().disconnect_controller(*self, driver, None).into_result()
+	}
+
+	pub fn disconnect_child(&self, driver: Handle, child: Handle) -> Result<()> {
+		get_boot_services().disconnect_controller(*self, Some(driver), Some(child)).into_result()
+	}
+
+	pub fn supports<T: protocol::Protocol>(&self) -> bool {
+		unsafe {
+			get_boot_services().open_protocol(*self, &<T as protocol::Protocol>::guid(), get_current_image(), Handle::null(), protocol::OPEN_PROTOCOL_TEST_PROTOCOL).is_ok()
 		}
 	}
 }
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Time { // apparently we're just too cool to use a normal goddamn UNIX timestamp, or any other format remotely sensible to store time internally in
-	year: u16,
-	month: u8,
-	day: u8,
-	hour: u8,
-	minute: u8,
-	second: u8,
+	pub year: u16,
+	pub month: u8,
+	pub day: u8,
+	pub hour: u8,
+	pub minute: u8,
+	pub second: u8,
 	pad1: u8,
-	nanosecond: u32,
-	time_zone: i16,
-	daylight: u8,
+	pub nanosecond: u32,
+	pub time_zone: i16, // minutes from UTC, or TIME_ZONE_UNSPECIFIED for local time
+	pub daylight: u8,
 	pad2: u8
 }
+
+pub const TIME_ZONE_UNSPECIFIED: i16 = 0x07FF;
+
+// bits in Time::daylight
+pub const TIME_ADJUST_DAYLIGHT: u8 = 0x01;
+pub const TIME_IN_DAYLIGHT: u8 = 0x02;
+
+impl Time {
+	// local time, without any daylight saving information
+	pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8, nanosecond: u32) -> Time {
+		Time {
+			year: year,
+			month: month,
+			day: day,
+			hour: hour,
+			minute: minute,
+			second: second,
+			pad1: 0,
+			nanosecond: nanosecond,
+			time_zone: TIME_ZONE_UNSPECIFIED,
+			daylight: 0,
+			pad2: 0
+		}
+	}
+}
+
+#[allow(dead_code)]
+fn assert_layout() {
+	assert_size!(Guid, 16, 16);
+	assert_size!(Handle, 4, 8);
+	assert_size!(Status, 4, 8);
+	assert_size!(Time, 16, 16);
+}
@@ -4,18 +4,21 @@ use libc;
 
 use table::{MemoryType, AllocType};
 
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern fn malloc(size: libc::size_t) -> *mut libc::c_void {
 	::get_boot_services().alloc(MemoryType::LoaderData, size as usize).expect("out of memory") as *mut libc::c_void
 }
 
 // TODO: care about the alignment
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern fn posix_memalign(ptr: *mut *mut libc::c_void, align: libc::size_t, size: libc::size_t) -> libc::c_int {
 	*ptr = malloc(size);
 	0
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern fn realloc(old: *mut libc::c_void, size: libc::size_t) -> *mut libc::c_void {
 	let ptr = malloc(size);
@@ -25,6 +28,7 @@ pub unsafe extern fn realloc(old: *mut libc::c_void, size: libc::size_t) -> *mut
 	ptr
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern fn free(ptr: *mut libc::c_void) {
 	::get_boot_services().free(ptr as *mut ());
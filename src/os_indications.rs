@@ -0,0 +1,71 @@
+use core::prelude::*;
+
+use table::ResetType;
+use variable::{self, GLOBAL_VARIABLE, VARIABLE_NON_VOLATILE, VARIABLE_BOOTSERVICE_ACCESS, VARIABLE_RUNTIME_ACCESS};
+use Status;
+
+// requests the os makes of the firmware for the next boot, through the OsIndications variable
+efi_flags!(OsIndications: u64 {
+	OS_INDICATIONS_BOOT_TO_FW_UI = 0x01,
+	OS_INDICATIONS_TIMESTAMP_REVOCATION = 0x02,
+	OS_INDICATIONS_FILE_CAPSULE_DELIVERY_SUPPORTED = 0x04,
+	OS_INDICATIONS_FMP_CAPSULE_SUPPORTED = 0x08,
+	OS_INDICATIONS_CAPSULE_RESULT_VAR_SUPPORTED = 0x10,
+	OS_INDICATIONS_START_OS_RECOVERY = 0x20,
+	OS_INDICATIONS_START_PLATFORM_RECOVERY = 0x40,
+	OS_INDICATIONS_JSON_CONFIG_DATA_REFRESH = 0x80,
+});
+
+// both variables are a single u64, a missing one means no bits set
+fn read(name: &str) -> ::Result<OsIndications> {
+	let mut data = [0u8; 8];
+	match variable::get(name, &GLOBAL_VARIABLE, &mut data) {
+		Ok((8, _)) => { },
+		Ok(_) => return Err(Status::VolumeCorrupted),
+		Err(Status::NotFound) => return Ok(OsIndications::empty()),
+		Err(error) => return Err(error)
+	}
+	let value = data.iter().rev().fold(0, |value, byte| value << 8 | *byte as u64);
+	Ok(OsIndications::from_bits(value))
+}
+
+// what the firmware understands
+pub fn supported() -> ::Result<OsIndications> {
+	read("OsIndicationsSupported")
+}
+
+// what is currently requested, the firmware clears the bits it acted on during the next boot
+pub fn pending() -> ::Result<OsIndications> {
+	read("OsIndications")
+}
+
+pub fn set(indications: OsIndications) -> ::Result<()> {
+	let value = indications.bits();
+	let mut data = [0u8; 8];
+	for i in 0..8 {
+		data[i] = (value >> (i * 8)) as u8;
+	}
+	variable::set("OsIndications", &GLOBAL_VARIABLE, VARIABLE_NON_VOLATILE | VARIABLE_BOOTSERVICE_ACCESS | VARIABLE_RUNTIME_ACCESS, &data)
+}
+
+// adds to what is already pending, fails with Unsupported if the firmware doesn't advertise all of them
+pub fn request(indications: OsIndications) -> ::Result<()> {
+	if !try!(supported()).contains(indications) {
+		return Err(Status::Unsupported);
+	}
+	let pending = try!(pending());
+	set(pending | indications)
+}
+
+pub fn cancel(indications: OsIndications) -> ::Result<()> {
+	let pending = try!(pending());
+	set(pending & !indications)
+}
+
+// the "restart to firmware settings" entry of os boot menus, only returns if the request failed
+pub fn reboot_to_firmware_ui() -> Status {
+	if let Err(error) = request(OS_INDICATIONS_BOOT_TO_FW_UI) {
+		return error;
+	}
+	::reset::reset(ResetType::Cold, Status::Success, None)
+}
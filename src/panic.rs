@@ -1,22 +1,53 @@
 use core::prelude::*;
 use core::fmt;
 
+use table::ResetType;
 use stdio;
+use Status;
 
+// what happens after the panic message is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+	Hang,
+	Reset(ResetType)
+}
+
+static mut policy: PanicPolicy = PanicPolicy::Hang;
+static mut panicking: bool = false;
+
+pub fn set_policy(new: PanicPolicy) {
+	unsafe {
+		policy = new;
+	}
+}
+
+#[cfg(not(test))]
 #[lang="panic_fmt"]
 extern fn panic_fmt(msg: fmt::Arguments, file: &'static str, line: u32) -> ! {
+	// a panic in a shutdown hook run by the reset below must not start all over again
+	if unsafe { panicking } {
+		loop { }
+	}
+	unsafe {
+		panicking = true;
+	}
+
 	println!("Panic at {}:{}: ", file, line); // TODO: print to stderr
 	stdio::println(msg);
 
-	// TODO: exit gracefully
-	loop { }
+	match unsafe { policy } {
+		PanicPolicy::Hang => loop { },
+		PanicPolicy::Reset(typ) => ::reset::reset(typ, Status::Aborted, None)
+	}
 }
 
+#[cfg(not(test))]
 #[lang="stack_exhausted"]
 extern fn stack_exhausted() {
 	loop { }
 }
 
+#[cfg(not(test))]
 #[lang="eh_personality"]
 extern fn eh_personality() {
 	loop { }
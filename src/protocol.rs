@@ -3,6 +3,7 @@ use core::mem::{size_of, uninitialized};
 use core::marker::PhantomData;
 use core::ptr;
 use core::slice;
+use core::ops::Deref;
 use io::{Read, Seek, SeekFrom, EndOfFile};
 
 use ::{Status, Table, Handle, Guid, Time};
@@ -12,6 +13,75 @@ pub trait Protocol {
 	fn guid() -> Guid;
 }
 
+efi_flags!(OpenAttributes: u32 {
+	OPEN_PROTOCOL_BY_HANDLE_PROTOCOL = 0x01,
+	OPEN_PROTOCOL_GET_PROTOCOL = 0x02,
+	OPEN_PROTOCOL_TEST_PROTOCOL = 0x04,
+	OPEN_PROTOCOL_BY_CHILD_CONTROLLER = 0x08,
+	OPEN_PROTOCOL_BY_DRIVER = 0x10,
+	OPEN_PROTOCOL_EXCLUSIVE = 0x20,
+});
+
+// an opened protocol interface, closed again when this goes away
+pub struct ScopedProtocol<T: Protocol> {
+	interface: *mut T,
+	handle: Handle,
+	agent: Handle,
+	controller: Handle
+}
+
+impl<T: Protocol> ScopedProtocol<T> {
+	// test opens don't return an interface, use Handle::supports for those
+	pub fn open(handle: Handle, agent: Handle, controller: Handle, attributes: OpenAttributes) -> ::Result<ScopedProtocol<T>> {
+		if attributes.contains(OPEN_PROTOCOL_TEST_PROTOCOL) {
+			return Err(::Status::InvalidParameter);
+		}
+		let interface = try!(unsafe { ::get_boot_services().open_protocol(handle, &<T as Protocol>::guid(), agent, controller, attributes) });
+		let protocol = ScopedProtocol {
+			interface: interface as *mut T,
+			handle: handle,
+			agent: agent,
+			controller: controller
+		};
+		// marker protocols are installed with a null interface, there's nothing to deref then.
+		// dropping protocol closes the open again
+		if protocol.interface.is_null() {
+			return Err(::Status::NotFound);
+		}
+		Ok(protocol)
+	}
+
+	pub fn handle(&self) -> Handle {
+		self.handle
+	}
+
+	// there's no DerefMut, non-exclusive opens can hand out the same interface any number of times,
+	// so writing through this is up to the caller to make safe
+	pub unsafe fn as_mut_ptr(&self) -> *mut T {
+		self.interface
+	}
+}
+
+impl<T: Protocol> Deref for ScopedProtocol<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		unsafe {
+			&*self.interface
+		}
+	}
+}
+
+impl<T: Protocol> Drop for ScopedProtocol<T> {
+	fn drop(&mut self) {
+		if ::boot_services_active() {
+			unsafe {
+				::get_boot_services().close_protocol(self.handle, &<T as Protocol>::guid(), self.agent, self.controller);
+			}
+		}
+	}
+}
+
 #[repr(C)]
 pub struct SimpleTextInput; // TODO
 
@@ -36,7 +106,7 @@ impl Protocol for SimpleTextOutput {
 
 impl SimpleTextOutput {
 	pub fn reset(&self, extended_verification: bool) -> Status {
-		(self.reset)(self as *const SimpleTextOutput, extended_verification)
+		Status((self.reset)(self as *const SimpleTextOutput, extended_verification))
 	}
 
 	pub fn print(&self, string: &str) -> Status {
@@ -46,7 +116,7 @@ impl SimpleTextOutput {
 			buffer_cursor += c.encode_utf16(&mut buffer[buffer_cursor..]).unwrap();
 			if buffer_cursor >= 128 {
 				buffer[buffer_cursor] = 0;
-				match (self.output_string)(self as *const SimpleTextOutput, buffer.as_ptr()) {
+				match Status((self.output_string)(self as *const SimpleTextOutput, buffer.as_ptr())) {
 					::Status::Success => { },
 					error => return error
 				}
@@ -55,13 +125,66 @@ impl SimpleTextOutput {
 
 		if buffer_cursor > 0 {
 			buffer[buffer_cursor] = 0;
-			(self.output_string)(self as *const SimpleTextOutput, buffer.as_ptr())
+			Status((self.output_string)(self as *const SimpleTextOutput, buffer.as_ptr()))
 		} else {
 			::Status::Success
 		}
 	}
 }
 
+// just the header of a node, the node data follows it and the path goes on until an end node
+#[repr(C)]
+pub struct DevicePath {
+	pub typ: u8,
+	pub sub_type: u8,
+	length: [u8; 2]
+}
+
+impl Protocol for DevicePath {
+	fn guid() -> Guid {
+		Guid(0x09576E91, 0x6D3F, 0x11D2, 0x8E, 0x39, 0x00, 0xA0, 0xC9, 0x69, 0x72, 0x3B)
+	}
+}
+
+impl DevicePath {
+	pub fn len(&self) -> usize {
+		self.length[0] as usize | (self.length[1] as usize) << 8
+	}
+
+	pub fn is_end(&self) -> bool {
+		self.typ == 0x7F && self.sub_type == 0xFF
+	}
+
+	// a node shorter than its own header is malformed, stepping over it would go nowhere or backwards
+	pub fn next(&self) -> Option<&DevicePath> {
+		if self.is_end() || self.len() < 4 {
+			return None;
+		}
+		unsafe {
+			Some(&*((self as *const DevicePath as *const u8).offset(self.len() as isize) as *const DevicePath))
+		}
+	}
+
+	// size of the whole path in bytes, including the end node
+	pub fn total_len(&self) -> usize {
+		let mut node = self;
+		let mut size = 0;
+		loop {
+			size += node.len();
+			match node.next() {
+				Some(next) => node = next,
+				None => return size
+			}
+		}
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		unsafe {
+			slice::from_raw_parts(self as *const DevicePath as *const u8, self.total_len())
+		}
+	}
+}
+
 #[repr(C)]
 pub struct LoadedImage<'a> {
 	revision: u32,
@@ -88,6 +211,25 @@ impl<'a> LoadedImage<'a> {
 	pub fn get_device(&self) -> Handle {
 		self.device_handle
 	}
+
+	pub fn get_parent(&self) -> Handle {
+		self.parent_handle
+	}
+
+	pub fn get_load_options(&self) -> &[u8] {
+		if self.load_options.is_null() {
+			return &[];
+		}
+		unsafe {
+			slice::from_raw_parts(self.load_options as *const u8, self.load_options_size as usize)
+		}
+	}
+
+	// the image reads these when it starts, so they have to stay alive until then
+	pub unsafe fn set_load_options(&mut self, options: *const (), size: u32) {
+		self.load_options = options;
+		self.load_options_size = size;
+	}
 }
 
 #[repr(C)]
@@ -105,7 +247,7 @@ impl Protocol for SimpleFileSystem {
 impl SimpleFileSystem {
 	pub fn open(&self) -> Option<Directory> {
 		let mut file: *const FileProtocol = ptr::null();
-		if (self.open)(self as *const SimpleFileSystem, &mut file as *mut *const FileProtocol) != ::Status::Success {
+		if Status((self.open)(self as *const SimpleFileSystem, &mut file as *mut *const FileProtocol)) != ::Status::Success {
 			return None;
 		}
 		Some(Directory {
@@ -129,6 +271,7 @@ struct FileProtocol { // not even a proper protocol, doesnt have a GUID (afaik),
 }
 
 static FILE_INFO_GUID: Guid = Guid(0x09576E92, 0x6D3F, 0x11D2, 0x8E, 0x39, 0x00, 0xA0, 0xC9, 0x69, 0x72, 0x3B);
+#[repr(C)]
 struct FileInfo {
 	size: u64,
 	file_size: u64,
@@ -161,12 +304,12 @@ impl Directory {
 		}
 		buffer[buffer_cursor] = 0;
 		let mut file_protocol = 0 as *const FileProtocol;
-		if (unsafe { &*self.protocol }.open)(self.protocol, &mut file_protocol as *mut *const FileProtocol, buffer.as_ptr(), 1, 0) != ::Status::Success {
+		if Status((unsafe { &*self.protocol }.open)(self.protocol, &mut file_protocol as *mut *const FileProtocol, buffer.as_ptr(), 1, 0)) != ::Status::Success {
 			return OpenResult::None;
 		}
 		let mut info: FileInfo = unsafe { uninitialized() };
 		let mut size = size_of::<FileInfo>();
-		if (unsafe { &*file_protocol }.get_info)(file_protocol, &FILE_INFO_GUID as *const Guid, &mut size as *mut usize, &mut info as *mut FileInfo) != ::Status::Success {
+		if Status((unsafe { &*file_protocol }.get_info)(file_protocol, &FILE_INFO_GUID as *const Guid, &mut size as *mut usize, &mut info as *mut FileInfo)) != ::Status::Success {
 			panic!("could not read file info")
 		}
 		if info.attributes & 0x10 > 0 {
@@ -209,7 +352,7 @@ impl File {
 	pub fn size(&self) -> u64 {
 		let mut info: FileInfo = unsafe { uninitialized() };
 		let mut size = size_of::<FileInfo>();
-		if (unsafe { &*self.protocol }.get_info)(self.protocol, &FILE_INFO_GUID as *const Guid, &mut size as *mut usize, &mut info as *mut FileInfo) != ::Status::Success {
+		if Status((unsafe { &*self.protocol }.get_info)(self.protocol, &FILE_INFO_GUID as *const Guid, &mut size as *mut usize, &mut info as *mut FileInfo)) != ::Status::Success {
 			panic!("could not read file info")
 		}
 		info.file_size
@@ -221,7 +364,7 @@ impl Read for File {
 
 	fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndOfFile> {
 		let mut length = buf.len();
-		if (unsafe { &*self.protocol }.read)(self.protocol, &mut length as *mut usize, buf.as_mut_ptr()) != ::Status::Success {
+		if Status((unsafe { &*self.protocol }.read)(self.protocol, &mut length as *mut usize, buf.as_mut_ptr())) != ::Status::Success {
 			return Err(EndOfFile); // TODO: handle this correctly
 		}
 		if length == 0 {
@@ -236,7 +379,7 @@ impl Seek for File {
 
 	fn tell(&mut self) -> Result<u64, ()> {
 		let mut pos = 0;
-		if (unsafe { &*self.protocol }.get_position)(self.protocol, &mut pos as *mut u64) != ::Status::Success {
+		if Status((unsafe { &*self.protocol }.get_position)(self.protocol, &mut pos as *mut u64)) != ::Status::Success {
 			return Err(()); // TODO: handle this correctly
 		}
 		Ok(pos)
@@ -248,13 +391,22 @@ impl Seek for File {
 			SeekFrom::End(offset) => (self.size() as i64 + offset) as u64,
 			SeekFrom::Current(offset) => (self.tell().unwrap() as i64 + offset) as u64
 		};
-		if (unsafe { &*self.protocol }.set_position)(self.protocol, pos) != ::Status::Success {
+		if Status((unsafe { &*self.protocol }.set_position)(self.protocol, pos)) != ::Status::Success {
 			return Err(()); // TODO: handle this correctly
 		}
 		Ok(pos)
 	}
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PixelBitmask {
+	pub red: u32,
+	pub green: u32,
+	pub blue: u32,
+	pub reserved: u32
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct ModeInfo {
@@ -262,7 +414,7 @@ pub struct ModeInfo {
 	pub x_res: u32,
 	pub y_res: u32,
 	pub pixel_format: u32,
-	pub bitmask: (u32, u32, u32, u32),
+	pub bitmask: PixelBitmask,
 	pub stride: u32
 }
 
@@ -276,7 +428,7 @@ struct GraphicsMode {
 	framebuffer_size: usize
 }
 
-#[repr(usize)]
+#[repr(u32)]
 enum BlitMode {
 	Fill = 0,
 	VideoToBuffer = 1,
@@ -330,3 +482,48 @@ impl GraphicsOutput {
 		(self.blit)(self, &color as *const u32 as *mut u32, BlitMode::Fill, 0, 0, x, y, width, height, 0);
 	}
 }
+
+// the ones marked as prefixes only declare the start of the firmware's struct
+#[allow(dead_code)]
+fn assert_layout() {
+	assert_size!(SimpleTextOutput, 8, 16); // prefix
+	assert_size!(DevicePath, 4, 4);
+	assert_size!(LoadedImage<'static>, 32, 64); // prefix
+	assert_size!(SimpleFileSystem, 12, 16);
+	assert_size!(FileProtocol, 40, 72); // prefix
+	assert_size!(FileInfo, 336, 336);
+	assert_size!(ModeInfo, 36, 36);
+	assert_size!(GraphicsMode, 28, 40);
+	assert_size!(GraphicsOutput, 16, 32);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{LoadedImage, ModeInfo, GraphicsMode};
+
+	#[test]
+	fn layout() {
+		assert_offset!(LoadedImage<'static>, revision, 0, 0);
+		assert_offset!(LoadedImage<'static>, parent_handle, 4, 8);
+		assert_offset!(LoadedImage<'static>, system_table, 8, 16);
+		assert_offset!(LoadedImage<'static>, device_handle, 12, 24);
+		assert_offset!(LoadedImage<'static>, device_path, 16, 32);
+		assert_offset!(LoadedImage<'static>, reserved, 20, 40);
+		assert_offset!(LoadedImage<'static>, load_options_size, 24, 48);
+		assert_offset!(LoadedImage<'static>, load_options, 28, 56);
+
+		assert_offset!(ModeInfo, version, 0, 0);
+		assert_offset!(ModeInfo, x_res, 4, 4);
+		assert_offset!(ModeInfo, y_res, 8, 8);
+		assert_offset!(ModeInfo, pixel_format, 12, 12);
+		assert_offset!(ModeInfo, bitmask, 16, 16);
+		assert_offset!(ModeInfo, stride, 32, 32);
+
+		assert_offset!(GraphicsMode, max_mode, 0, 0);
+		assert_offset!(GraphicsMode, mode, 4, 4);
+		assert_offset!(GraphicsMode, mode_info, 8, 8);
+		assert_offset!(GraphicsMode, info_size, 12, 16);
+		assert_offset!(GraphicsMode, framebuffer_base, 16, 24);
+		assert_offset!(GraphicsMode, framebuffer_size, 24, 32);
+	}
+}
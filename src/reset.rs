@@ -0,0 +1,66 @@
+use core::prelude::*;
+use core::{mem, ptr};
+
+use table::ResetType;
+use ::{Status, Guid};
+
+// while boot services are still around the shutdown hooks get their turn first, after
+// exit_boot_services this is a plain runtime services call, so it never allocates
+pub fn reset(typ: ResetType, status: Status, data: Option<&[u8]>) -> ! {
+	if ::boot_services_active() {
+		::shutdown::run_hooks();
+	}
+	let (size, data) = match data {
+		Some(data) => (data.len(), data.as_ptr()),
+		None => (0, ptr::null())
+	};
+	unsafe {
+		::get_runtime_services().reset_system(typ, status, size, data)
+	}
+}
+
+pub fn reboot() -> ! {
+	reset(ResetType::Cold, Status::Success, None)
+}
+
+pub fn shutdown() -> ! {
+	reset(ResetType::Shutdown, Status::Success, None)
+}
+
+// writes the reset data for ResetType::PlatformSpecific into buffer: the description as a null
+// terminated utf-16 string, the guid naming the reset and then extra, returns the size written
+pub fn platform_reset_data(buffer: &mut [u8], description: &str, guid: &Guid, extra: &[u8]) -> ::Result<usize> {
+	let mut cursor = 0;
+	{
+		let mut push = |bytes: &[u8]| {
+			if cursor + bytes.len() > buffer.len() {
+				return Err(Status::BufferTooSmall);
+			}
+			for (i, byte) in bytes.iter().enumerate() {
+				buffer[cursor + i] = *byte;
+			}
+			cursor += bytes.len();
+			Ok(())
+		};
+		for c in description.chars() {
+			let mut units = [0; 2];
+			let length = c.encode_utf16(&mut units).unwrap();
+			for unit in units[..length].iter() {
+				try!(push(&[*unit as u8, (*unit >> 8) as u8]));
+			}
+		}
+		try!(push(&[0, 0]));
+		let guid: [u8; 16] = unsafe { mem::transmute(*guid) };
+		try!(push(&guid));
+		try!(push(extra));
+	}
+	Ok(cursor)
+}
+
+// a platform specific reset with no description or extra data, firmware that doesn't know the guid
+// does a cold reset instead
+pub fn reset_platform_specific(guid: &Guid, status: Status) -> ! {
+	let mut buffer = [0; 18];
+	let size = platform_reset_data(&mut buffer, "", guid, &[]).unwrap();
+	reset(ResetType::PlatformSpecific, status, Some(&buffer[..size]))
+}
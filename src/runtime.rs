@@ -0,0 +1,139 @@
+use core::prelude::*;
+use core::{cmp, mem, ptr};
+
+use event::{Event, EVT_NOTIFY_SIGNAL, EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE};
+use table::{Tpl, MemoryMap, MemoryType, MemoryDescriptor, MEMORY_RUNTIME};
+use Status;
+
+// lets ConvertPointer pass null pointers through instead of failing
+const OPTIONAL_POINTER: usize = 0x1;
+
+// the closure lives in RuntimeServicesData rather than on the heap (which is LoaderData and belongs to
+// the os by the time the notify runs), so it is called through its own trampoline instead of the
+// one Event uses
+#[cfg(target_pointer_width="32")]
+extern "system" fn trampoline<F: FnMut()>(_event: *const (), context: *mut ()) {
+	unsafe { (*(context as *mut F))() }
+}
+
+#[cfg(target_pointer_width="64")]
+extern "win64" fn trampoline<F: FnMut()>(_event: *const (), context: *mut ()) {
+	unsafe { (*(context as *mut F))() }
+}
+
+// a registration for the virtual address change, dropping it unregisters, runtime drivers leak() it
+pub struct VirtualAddressChange<F: FnMut() + 'static> {
+	event: Option<Event>,
+	notify: *mut F
+}
+
+// for runtime drivers: notify runs when the os calls SetVirtualAddressMap, long after
+// exit_boot_services, so it may only use runtime services. the closure itself is moved into runtime
+// memory, but anything it points to has to be there as well
+pub fn on_virtual_address_change<F: FnMut() + 'static>(notify: F) -> ::Result<VirtualAddressChange<F>> {
+	let boot_services = ::get_boot_services();
+	// pool memory is 8 byte aligned, which covers anything a closure holds
+	let notify_ptr = match boot_services.alloc(MemoryType::RuntimeServicesData, cmp::max(mem::size_of::<F>(), 1)) {
+		Some(ptr) => ptr as *mut F,
+		None => return Err(Status::OutOfResources)
+	};
+	unsafe {
+		ptr::write(notify_ptr, notify);
+		match boot_services.create_event_ex(EVT_NOTIFY_SIGNAL, Tpl::Notify, Some(trampoline::<F>), notify_ptr as *mut (), Some(&EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE)) {
+			Ok(event) => Ok(VirtualAddressChange {
+				event: Some(Event::from_raw(event)),
+				notify: notify_ptr
+			}),
+			Err(error) => {
+				drop(ptr::read(notify_ptr));
+				boot_services.free(notify_ptr as *mut ());
+				Err(error)
+			}
+		}
+	}
+}
+
+impl<F: FnMut() + 'static> VirtualAddressChange<F> {
+	pub fn event(&self) -> &Event {
+		self.event.as_ref().unwrap()
+	}
+
+	pub fn leak(self) {
+		mem::forget(self);
+	}
+}
+
+impl<F: FnMut() + 'static> Drop for VirtualAddressChange<F> {
+	fn drop(&mut self) {
+		if !::boot_services_active() {
+			return;
+		}
+		// close the event before the closure it points at goes away
+		drop(self.event.take());
+		unsafe {
+			drop(ptr::read(self.notify));
+			::get_boot_services().free(self.notify as *mut ());
+		}
+	}
+}
+
+// these are only valid from within a virtual address change notify function, null stays null
+pub unsafe fn convert_pointer<T>(pointer: &mut *const T) -> ::Result<()> {
+	::get_runtime_services().convert_pointer(OPTIONAL_POINTER, pointer as *mut *const T as *mut *const ()).into_result()
+}
+
+pub unsafe fn convert_mut_pointer<T>(pointer: &mut *mut T) -> ::Result<()> {
+	::get_runtime_services().convert_pointer(OPTIONAL_POINTER, pointer as *mut *mut T as *mut *const ()).into_result()
+}
+
+// converts the crate's own system table and runtime services pointers, a runtime driver calls this
+// once from its notify function so the crate keeps working in virtual mode
+pub unsafe fn convert_globals() -> ::Result<()> {
+	// the notify still runs with physical addresses, so hold on to the table while converting it
+	let services = ::get_runtime_services();
+	try!(services.convert_pointer(OPTIONAL_POINTER, &mut ::system_table as *mut _ as *mut *const ()).into_result());
+	services.convert_pointer(OPTIONAL_POINTER, &mut ::runtime_services as *mut _ as *mut *const ()).into_result()
+}
+
+// for os loaders, after exit_boot_services: gives every runtime descriptor of map the virtual address
+// translate picks for it, collects those into buffer and switches the firmware over, returning how many
+// there were. the crate's pointers get switched too, so runtime services can only be called again
+// once the new mappings are live
+pub fn set_virtual_address_map<F: FnMut(&MemoryDescriptor) -> u64>(map: &mut MemoryMap, buffer: &mut [MemoryDescriptor], mut translate: F) -> ::Result<usize> {
+	if ::boot_services_active() {
+		return Err(Status::Unsupported);
+	}
+	let mut count = 0;
+	for i in 0..map.get_descriptor_count() {
+		let descriptor = map.get_descriptor_mut(i);
+		if !descriptor.attributes().contains(MEMORY_RUNTIME) {
+			continue;
+		}
+		if count == buffer.len() {
+			return Err(Status::BufferTooSmall);
+		}
+		let virt = translate(&*descriptor);
+		descriptor.virt = virt;
+		buffer[count] = *descriptor;
+		count += 1;
+	}
+
+	let descriptor_size = mem::size_of::<MemoryDescriptor>();
+	let descriptors = &mut buffer[..count];
+	try!(unsafe { ::get_runtime_services().set_virtual_address_map(count * descriptor_size, descriptor_size, map.descriptor_version(), descriptors.as_mut_ptr()) }.into_result());
+	unsafe {
+		if let Some(address) = to_virtual(descriptors, ::system_table as usize as u64) {
+			::system_table = address as usize as *const _;
+		}
+		if let Some(address) = to_virtual(descriptors, ::runtime_services as usize as u64) {
+			::runtime_services = address as usize as *const _;
+		}
+	}
+	Ok(count)
+}
+
+fn to_virtual(descriptors: &[MemoryDescriptor], address: u64) -> Option<u64> {
+	descriptors.iter()
+		.find(|descriptor| descriptor.phys <= address && address < descriptor.end())
+		.map(|descriptor| descriptor.virt + (address - descriptor.phys))
+}
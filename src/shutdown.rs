@@ -0,0 +1,62 @@
+use core::prelude::*;
+use core::mem;
+use alloc::boxed::Box;
+use collections::Vec;
+
+use table::MemoryMap;
+use Status;
+
+type Hook = Box<FnMut() + 'static>;
+
+static mut hooks: *mut Vec<Hook> = 0 as *mut Vec<Hook>;
+
+// hooks run once, in the order they were registered, when efi_main returns, on image::exit and on
+// exit_boot_services below. they still have boot services, so this is the place to close files,
+// flush logs, restore the console mode and uninstall protocols
+pub fn register<F: FnMut() + 'static>(hook: F) {
+	unsafe {
+		if hooks.is_null() {
+			hooks = mem::transmute(Box::new(Vec::<Hook>::new()));
+		}
+		(*hooks).push(Box::new(hook));
+	}
+}
+
+pub fn run_hooks() {
+	unsafe {
+		if hooks.is_null() {
+			return;
+		}
+		// hooks may register more hooks, those run too
+		while !(*hooks).is_empty() {
+			let pending = mem::replace(&mut *hooks, Vec::new());
+			for mut hook in pending.into_iter() {
+				(*hook)();
+			}
+		}
+	}
+}
+
+// runs the hooks and leaves boot services behind for good, handing back the final memory map
+pub fn exit_boot_services() -> ::Result<MemoryMap> {
+	run_hooks();
+	let boot_services = ::get_boot_services();
+	let mut map = try!(boot_services.memory_map());
+	loop {
+		let status = unsafe { boot_services.exit_boot_services(::get_current_image(), map.key()) };
+		if !status.is_error() {
+			return Ok(map);
+		}
+		// once ExitBootServices has been called all that's allowed is GetMemoryMap and calling it again,
+		// so the map's buffer can't be freed on the way out either
+		if status != Status::InvalidParameter {
+			mem::forget(map);
+			return Err(status);
+		}
+		// the map changed in between (notify functions can allocate, and may do so again each time)
+		if let Err(error) = map.refresh() {
+			mem::forget(map);
+			return Err(error);
+		}
+	}
+}
@@ -1,75 +1,212 @@
 use core::ptr;
-use core::mem::{size_of, uninitialized};
+use core::slice;
+use core::fmt;
+use core::mem::{size_of, transmute};
 use collections::Vec;
 use ::{Status, Guid, Handle};
+use protocol::{Protocol, DevicePath, OpenAttributes};
+use table::{MemoryType, MemoryMap, ConfigTable};
+use event::EventType;
 
-#[repr(usize)]
-pub enum Tpl {
-	Application = 4,
-	Callback = 8,
-	Notify = 16,
-	HighLevel = 31
+// raise_tpl hands back whatever level the firmware is at, which needn't be one of the four named
+// ones, so this is a plain number like Status
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tpl(pub usize);
+
+#[allow(non_upper_case_globals)]
+impl Tpl {
+	pub const Application: Tpl = Tpl(4);
+	pub const Callback: Tpl = Tpl(8);
+	pub const Notify: Tpl = Tpl(16);
+	pub const HighLevel: Tpl = Tpl(31);
+}
+
+impl fmt::Debug for Tpl {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.0 {
+			4 => f.write_str("Application"),
+			8 => f.write_str("Callback"),
+			16 => f.write_str("Notify"),
+			31 => f.write_str("HighLevel"),
+			tpl => write!(f, "Tpl({})", tpl)
+		}
+	}
 }
 
-#[repr(usize)]
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TimerType {
 	Cancel = 0,
 	Periodic = 1,
 	Relative = 2
 }
 
-#[repr(C, packed)]
+pub type EventNotify = efi_fn!((*const (), *mut ()) -> ());
+
+// field order follows EFI_BOOT_SERVICES as of UEFI 2.0, nothing has been added since
+#[repr(C)]
 pub struct BootServices {
-	raise_tpl: efi_fn!(Tpl), // FIXME: this returns a Tpl, not a Status
-	restore_tpl: efi_fn!(Tpl), // FIXME: doesn't return anything
+	raise_tpl: efi_fn!((usize) -> usize),
+	restore_tpl: efi_fn!((usize) -> ()),
 
-	allocate_pages: efi_fn!(AllocType, MemoryType, usize, *mut u64),
+	allocate_pages: efi_fn!(AllocType, u32, usize, *mut u64),
 	free_pages: efi_fn!(u64, usize),
 	get_memory_map: efi_fn!(*mut usize, *mut (), *mut usize, *mut usize, *mut u32),
-	allocate_pool: efi_fn!(MemoryType, usize, *mut *mut ()),
+	allocate_pool: efi_fn!(u32, usize, *mut *mut ()),
 	free_pool: efi_fn!(*mut ()),
 
-	create_event: efi_fn!(usize, Tpl, efi_fn!(), usize, *mut *const ()), // TODO: first arg should be an EventType bitfield
+	create_event: efi_fn!(u32, usize, Option<EventNotify>, *mut (), *mut *const ()),
 	set_timer: efi_fn!(*const (), TimerType, u64),
 	wait_for_event: efi_fn!(usize, *const *const (), *mut usize),
 	signal_event: efi_fn!(*const ()),
 	close_event: efi_fn!(*const ()),
 	check_event: efi_fn!(*const ()),
 
-	install_protocol_interface: *const (),
-	reinstall_protocol_interface: *const (),
-	uninstall_protocol_interface: *const (),
+	install_protocol_interface: efi_fn!(*mut Handle, *const Guid, u32, *mut ()),
+	reinstall_protocol_interface: efi_fn!(Handle, *const Guid, *mut (), *mut ()),
+	uninstall_protocol_interface: efi_fn!(Handle, *const Guid, *mut ()),
 	handle_protocol: efi_fn!(Handle, &Guid, *mut *mut ()),
 	reserved: *const (),
-	register_protocol_notify: *const (),
+	register_protocol_notify: efi_fn!(*const Guid, *const (), *mut *const ()),
 	locate_handle: efi_fn!(SearchType, *const Guid, *const (), *mut usize, *mut Handle),
-	locate_device_path: *const (),
-	install_configuration_table: *const (),
+	locate_device_path: efi_fn!(*const Guid, *mut *const DevicePath, *mut Handle),
+	install_configuration_table: efi_fn!(*const Guid, *const ()),
 
-	load_image: *const (),
-	start_image: *const (),
-	exit: *const (),
-	unload_image: *const (),
+	load_image: efi_fn!(bool, Handle, *const DevicePath, *const u8, usize, *mut Handle),
+	start_image: efi_fn!(Handle, *mut usize, *mut *mut u16),
+	exit: efi_fn!(Handle, usize, usize, *mut u16),
+	unload_image: efi_fn!(Handle),
 	exit_boot_services: efi_fn!(Handle, usize),
 
-	// this is incomplete
+	get_next_monotonic_count: efi_fn!(*mut u64),
+	stall: efi_fn!(usize),
+	set_watchdog_timer: efi_fn!(usize, u64, usize, *const u16),
+
+	connect_controller: efi_fn!(Handle, *const Handle, *const DevicePath, bool),
+	disconnect_controller: efi_fn!(Handle, Handle, Handle),
+
+	open_protocol: efi_fn!(Handle, *const Guid, *mut *mut (), Handle, Handle, u32),
+	close_protocol: efi_fn!(Handle, *const Guid, Handle, Handle),
+	open_protocol_information: efi_fn!(Handle, *const Guid, *mut *mut OpenProtocolInformation, *mut usize),
+
+	protocols_per_handle: efi_fn!(Handle, *mut *mut *const Guid, *mut usize),
+	locate_handle_buffer: efi_fn!(SearchType, *const Guid, *const (), *mut usize, *mut *mut Handle),
+	locate_protocol: efi_fn!(*const Guid, *const (), *mut *mut ()),
+	// these two are variadic, see install_multiple_protocol_interfaces
+	install_multiple_protocol_interfaces: *const (),
+	uninstall_multiple_protocol_interfaces: *const (),
+
+	calculate_crc32: efi_fn!(*const u8, usize, *mut u32),
+
+	copy_mem: efi_fn!((*mut u8, *const u8, usize) -> ()),
+	set_mem: efi_fn!((*mut u8, usize, u8) -> ()),
+	create_event_ex: efi_fn!(u32, usize, Option<EventNotify>, *mut (), *const Guid, *mut *const ()),
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct OpenProtocolInformation {
+	pub agent_handle: Handle,
+	pub controller_handle: Handle,
+	pub attributes: u32,
+	pub open_count: u32
 }
 
 #[repr(u32)]
-enum SearchType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchType {
 	AllHandles = 0,
 	ByRegisterNotify = 1,
 	ByProtocol = 2,
 }
 
 impl BootServices {
+	// returns the previous tpl, raising to a lower level than the current one is not allowed
+	pub unsafe fn raise_tpl(&self, tpl: Tpl) -> Tpl {
+		Tpl((self.raise_tpl)(tpl.0))
+	}
+
+	pub unsafe fn restore_tpl(&self, tpl: Tpl) {
+		(self.restore_tpl)(tpl.0)
+	}
+
+	// installs on a new handle if handle is null, and writes the new handle back
+	pub unsafe fn install_protocol_interface(&self, handle: &mut Handle, guid: &Guid, interface: *mut ()) -> Status {
+		Status((self.install_protocol_interface)(handle, guid, 0, interface)) // 0 is EFI_NATIVE_INTERFACE, the only type there is
+	}
+
+	pub unsafe fn reinstall_protocol_interface(&self, handle: Handle, guid: &Guid, old: *mut (), new: *mut ()) -> Status {
+		Status((self.reinstall_protocol_interface)(handle, guid, old, new))
+	}
+
+	pub unsafe fn uninstall_protocol_interface(&self, handle: Handle, guid: &Guid, interface: *mut ()) -> Status {
+		Status((self.uninstall_protocol_interface)(handle, guid, interface))
+	}
+
+	// agent is the image doing the opening, controller the handle a driver manages (or null)
+	pub unsafe fn open_protocol(&self, handle: Handle, guid: &Guid, agent: Handle, controller: Handle, attributes: OpenAttributes) -> ::Result<*mut ()> {
+		let mut interface = ptr::null_mut();
+		try!(Status((self.open_protocol)(handle, guid, &mut interface, agent, controller, attributes.bits())).into_result());
+		Ok(interface)
+	}
+
+	pub unsafe fn close_protocol(&self, handle: Handle, guid: &Guid, agent: Handle, controller: Handle) -> Status {
+		Status((self.close_protocol)(handle, guid, agent, controller))
+	}
+
 	pub unsafe fn handle_protocol(&self, handle: Handle, guid: Guid, ptr: *mut *mut ()) -> Status {
-		(self.handle_protocol)(handle, &guid, ptr)
+		Status((self.handle_protocol)(handle, &guid, ptr))
+	}
+
+	pub unsafe fn create_event(&self, typ: EventType, tpl: Tpl, notify: Option<EventNotify>, context: *mut ()) -> ::Result<*const ()> {
+		let mut event = ptr::null();
+		try!(Status((self.create_event)(typ.bits(), tpl.0, notify, context, &mut event)).into_result());
+		Ok(event)
+	}
+
+	pub unsafe fn set_timer(&self, event: *const (), typ: TimerType, trigger_time: u64) -> Status {
+		Status((self.set_timer)(event, typ, trigger_time))
+	}
+
+	// returns the index of the event that was signalled
+	pub unsafe fn wait_for_event(&self, events: &[*const ()]) -> ::Result<usize> {
+		let mut index = 0;
+		try!(Status((self.wait_for_event)(events.len(), events.as_ptr(), &mut index)).into_result());
+		Ok(index)
+	}
+
+	pub unsafe fn signal_event(&self, event: *const ()) -> Status {
+		Status((self.signal_event)(event))
+	}
+
+	pub unsafe fn close_event(&self, event: *const ()) -> Status {
+		Status((self.close_event)(event))
+	}
+
+	pub unsafe fn check_event(&self, event: *const ()) -> Status {
+		Status((self.check_event)(event))
+	}
+
+	// the returned registration key is only good for passing to next_notified_handle
+	pub unsafe fn register_protocol_notify(&self, guid: &Guid, event: *const ()) -> ::Result<*const ()> {
+		let mut registration = ptr::null();
+		try!(Status((self.register_protocol_notify)(guid, event, &mut registration)).into_result());
+		Ok(registration)
+	}
+
+	// handles that got the protocol since the last call, one at a time
+	pub unsafe fn next_notified_handle(&self, registration: *const ()) -> Option<Handle> {
+		let mut handle = Handle::null();
+		let mut size = size_of::<Handle>();
+		if Status((self.locate_handle)(SearchType::ByRegisterNotify, ptr::null(), registration, &mut size, &mut handle)) != ::Status::Success {
+			return None;
+		}
+		Some(handle)
 	}
 
 	pub fn alloc(&self, typ: MemoryType, size: usize) -> Option<*mut ()> {
 		let mut ptr = ptr::null_mut();
-		if (self.allocate_pool)(typ, size, &mut ptr) != ::Status::Success {
+		if Status((self.allocate_pool)(typ.code(), size, &mut ptr)) != ::Status::Success {
 			return None;
 		}
 		Some(ptr)
@@ -81,7 +218,7 @@ impl BootServices {
 
 	pub unsafe fn alloc_pages(&self, alloc_type: AllocType, memory_type: MemoryType, count: usize, address: *mut ()) -> Option<*mut ()> {
 		let mut ptr = address as u64;
-		if (self.allocate_pages)(alloc_type, memory_type, count, &mut ptr) != ::Status::Success {
+		if Status((self.allocate_pages)(alloc_type, memory_type.code(), count, &mut ptr)) != ::Status::Success {
 			return None;
 		}
 		Some(ptr as *mut ())
@@ -91,152 +228,333 @@ impl BootServices {
 		(self.free_pages)(address as u64, count);
 	}
 
-	pub fn handles_by_protocol(&self, guid: &Guid) -> Vec<Handle> {
-		unsafe {
-			let mut results = vec![uninitialized(); 32];
-			loop {
-				let mut buffer_size = results.len() * size_of::<Handle>();
-				if (self.locate_handle)(SearchType::ByProtocol, &*guid, 0 as *const (), &mut buffer_size, results.as_mut_ptr()) == ::Status::Success {
-					results.set_len(buffer_size / size_of::<Handle>());
-					return results;
-				}
-				let len = results.len();
-				results.extend((0..len).map(|_| uninitialized::<Handle>()));
-				// TODO: handle other errors properly
+	fn locate_handle(&self, search: SearchType, guid: *const Guid, key: *const ()) -> ::Result<Vec<Handle>> {
+		let mut size = 0;
+		match Status((self.locate_handle)(search, guid, key, &mut size, ptr::null_mut())) {
+			Status::BufferTooSmall => { },
+			Status::Success | Status::NotFound => return Ok(Vec::new()),
+			error => return Err(error)
+		}
+		loop {
+			let mut results = Vec::with_capacity(size / size_of::<Handle>());
+			match Status((self.locate_handle)(search, guid, key, &mut size, results.as_mut_ptr())) {
+				Status::Success => {
+					unsafe { results.set_len(size / size_of::<Handle>()); }
+					return Ok(results);
+				},
+				Status::BufferTooSmall => { }, // more handles showed up in between, size has been updated
+				error => return Err(error)
 			}
 		}
 	}
 
-	pub fn memory_map(&self) -> (MemoryMap, usize) {
-		let mut size = 0;
-		let mut key = 0;
-		let mut descriptor_size = 0usize;
-		let mut descriptor_version = 0u32;
-		(self.get_memory_map)(&mut size, ptr::null_mut(), &mut key, &mut descriptor_size, &mut descriptor_version);
-		size += descriptor_size; // the allocation may end up inserting another entry
-		let mem = self.alloc(MemoryType::LoaderData, size).expect("out of memory");
-		if (self.get_memory_map)(&mut size as *mut usize, mem, &mut key as *mut usize, &mut descriptor_size as *mut usize, &mut descriptor_version) != ::Status::Success {
-			panic!("failed to fetch memory map")
+	pub fn handles_by_protocol(&self, guid: &Guid) -> ::Result<Vec<Handle>> {
+		self.locate_handle(SearchType::ByProtocol, guid, ptr::null())
+	}
+
+	pub fn all_handles(&self) -> ::Result<Vec<Handle>> {
+		self.locate_handle(SearchType::AllHandles, ptr::null(), ptr::null())
+	}
+
+	// same thing as the above, except that the firmware sizes and allocates the buffer itself. guid is
+	// for ByProtocol, key is the registration from register_protocol_notify for ByRegisterNotify
+	pub fn locate_handle_buffer(&self, search: SearchType, guid: Option<&Guid>, key: Option<*const ()>) -> ::Result<Vec<Handle>> {
+		let guid = guid.map(|guid| guid as *const Guid).unwrap_or(ptr::null());
+		let key = key.unwrap_or(ptr::null());
+		let mut count = 0;
+		let mut buffer = ptr::null_mut();
+		match Status((self.locate_handle_buffer)(search, guid, key, &mut count, &mut buffer)) {
+			Status::Success => { },
+			Status::NotFound => return Ok(Vec::new()),
+			error => return Err(error)
 		}
-		(MemoryMap {
-			mem: mem,
-			size: size,
-			descriptor_size: descriptor_size
-		}, key)
+		let handles = unsafe { slice::from_raw_parts(buffer, count).to_vec() };
+		unsafe { self.free(buffer as *mut ()); }
+		Ok(handles)
 	}
 
-	pub unsafe fn exit_boot_services(&self, image: Handle, key: usize) -> Status {
-		(self.exit_boot_services)(image, key)
+	pub fn protocols_per_handle(&self, handle: Handle) -> ::Result<Vec<Guid>> {
+		let mut buffer = ptr::null_mut();
+		let mut count = 0;
+		try!(Status((self.protocols_per_handle)(handle, &mut buffer, &mut count)).into_result());
+		let guids = unsafe { slice::from_raw_parts(buffer, count).iter().map(|guid| **guid).collect() };
+		unsafe { self.free(buffer as *mut ()); }
+		Ok(guids)
 	}
-}
 
-#[derive(Clone, Copy)]
-#[repr(C)]
-pub enum AllocType {
-	AnyPages,
-	MaxAddress,
-	Address,
-}
+	// who has the protocol open on handle and how
+	pub fn open_protocol_information(&self, handle: Handle, guid: &Guid) -> ::Result<Vec<OpenProtocolInformation>> {
+		let mut buffer = ptr::null_mut();
+		let mut count = 0;
+		try!(Status((self.open_protocol_information)(handle, guid, &mut buffer, &mut count)).into_result());
+		let entries = unsafe { slice::from_raw_parts(buffer, count).to_vec() };
+		unsafe { self.free(buffer as *mut ()); }
+		Ok(entries)
+	}
 
-#[derive(Debug, Clone, Copy)]
-#[repr(C)]
-pub enum MemoryType {
-	Reserved,
-	LoaderCode,
-	LoaderData,
-	BootServicesCode,
-	BootServicesData,
-	RuntimeServicesCode,
-	RuntimeServicesData,
-	Conventional,
-	Unusable,
-	AcpiReclaimable,
-	AcpiMemoryNvs,
-	MemoryMappedIo,
-	MemoryMappedIoPortSpace,
-	PalCode
-}
+	// the first instance of the protocol the firmware finds, for protocols that only exist once
+	pub fn locate_protocol<T: Protocol>(&self) -> ::Result<&T> {
+		let mut interface = ptr::null_mut();
+		try!(Status((self.locate_protocol)(&<T as Protocol>::guid(), ptr::null(), &mut interface)).into_result());
+		Ok(unsafe { &*(interface as *const T) })
+	}
 
-impl MemoryType {
-	pub fn from_code(code: u32) -> Option<MemoryType> {
-		Some(match code {
-			0 => MemoryType::Reserved,
-			1 => MemoryType::LoaderCode,
-			2 => MemoryType::LoaderData,
-			3 => MemoryType::BootServicesCode,
-			4 => MemoryType::BootServicesData,
-			5 => MemoryType::RuntimeServicesCode,
-			6 => MemoryType::RuntimeServicesData,
-			7 => MemoryType::Conventional,
-			8 => MemoryType::Unusable,
-			9 => MemoryType::AcpiReclaimable,
-			10 => MemoryType::AcpiMemoryNvs,
-			11 => MemoryType::MemoryMappedIo,
-			12 => MemoryType::MemoryMappedIoPortSpace,
-			13 => MemoryType::PalCode,
-			_ => return None
-		})
+	// finds the handle whose device path is the longest prefix of path and supports the protocol,
+	// also returns what is left of path after that prefix
+	pub fn locate_device_path<'a>(&self, guid: &Guid, path: &'a DevicePath) -> ::Result<(Handle, &'a DevicePath)> {
+		let mut remaining = path as *const DevicePath;
+		let mut handle = Handle::null();
+		try!(Status((self.locate_device_path)(guid, &mut remaining, &mut handle)).into_result());
+		Ok((handle, unsafe { &*remaining }))
 	}
-}
 
-#[repr(C)]
-pub struct MemoryDescriptor {
-	pub typ: u32,
-	pub pad: u32,
-	pub phys: u64,
-	pub virt: u64,
-	pub count: u64,
-	pub attribute: u64
-}
+	// a null table removes the entry for guid
+	pub unsafe fn install_configuration_table(&self, guid: &Guid, table: *const ()) -> Status {
+		Status((self.install_configuration_table)(guid, table))
+	}
 
-pub struct MemoryMap {
-	mem: *mut (),
-	size: usize,
-	descriptor_size: usize
-}
+	// copies table into pool memory of memory_type and publishes it, use a type the os keeps around
+	// (RuntimeServicesData, AcpiReclaimable or one of the Os ones) for handoff structures
+	pub fn publish_config_table<T: ConfigTable>(&self, table: T, memory_type: MemoryType) -> ::Result<*mut T> {
+		let ptr = match self.alloc(memory_type, size_of::<T>()) {
+			Some(ptr) => ptr as *mut T,
+			None => return Err(Status::OutOfResources)
+		};
+		unsafe {
+			ptr::write(ptr, table);
+			let status = self.install_configuration_table(&<T as ConfigTable>::guid(), ptr as *const ());
+			if status.is_error() {
+				self.free(ptr as *mut ());
+				return Err(status);
+			}
+		}
+		Ok(ptr)
+	}
 
-impl MemoryMap {
-	pub fn get_descriptor(&self, index: usize) -> &MemoryDescriptor {
-		if index * self.descriptor_size >= self.size {
-			panic!()
+	// the memory behind the table is left alone, it's the caller's to free
+	pub fn remove_config_table(&self, guid: &Guid) -> ::Result<()> {
+		unsafe { self.install_configuration_table(guid, ptr::null()) }.into_result()
+	}
+
+	// source may be null, in which case the image is read from path
+	// on SecurityViolation the image was loaded anyway and the handle is valid, it has to be either
+	// started or unloaded, so both are handed back
+	pub unsafe fn load_image(&self, boot_policy: bool, parent: Handle, path: *const DevicePath, source: *const u8, source_size: usize) -> (Status, Handle) {
+		let mut image = Handle::null();
+		let status = Status((self.load_image)(boot_policy, parent, path, source, source_size, &mut image));
+		(status, image)
+	}
+
+	// returns the exit status and exit data (pool memory the caller has to free)
+	pub unsafe fn start_image(&self, image: Handle) -> (Status, usize, *mut u16) {
+		let mut data_size = 0;
+		let mut data = ptr::null_mut();
+		let status = Status((self.start_image)(image, &mut data_size, &mut data));
+		(status, data_size, data)
+	}
+
+	// data has to come from the pool, this only returns if something went wrong
+	pub unsafe fn exit(&self, image: Handle, status: Status, data_size: usize, data: *mut u16) -> Status {
+		Status((self.exit)(image, status.0, data_size, data))
+	}
+
+	pub unsafe fn unload_image(&self, image: Handle) -> Status {
+		Status((self.unload_image)(image))
+	}
+
+	pub fn get_next_monotonic_count(&self) -> ::Result<u64> {
+		let mut count = 0;
+		try!(Status((self.get_next_monotonic_count)(&mut count)).into_result());
+		Ok(count)
+	}
+
+	pub fn stall(&self, microseconds: usize) -> Status {
+		Status((self.stall)(microseconds))
+	}
+
+	// timeout is in seconds, 0 disables the watchdog, data is a null terminated string optionally
+	// followed by binary data
+	pub unsafe fn set_watchdog_timer(&self, timeout: usize, code: u64, data_size: usize, data: *const u16) -> Status {
+		Status((self.set_watchdog_timer)(timeout, code, data_size, data))
+	}
+
+	// drivers None lets the firmware pick them, remaining_path limits which children get created
+	pub fn connect_controller(&self, controller: Handle, drivers: Option<&[Handle]>, remaining_path: Option<&DevicePath>, recursive: bool) -> Status {
+		let drivers = drivers.map(|drivers| {
+			let mut list = drivers.to_vec();
+			list.push(Handle::null()); // the list is null terminated
+			list
+		});
+		let drivers_ptr = drivers.as_ref().map(|drivers| drivers.as_ptr()).unwrap_or(ptr::null());
+		let remaining_path = remaining_path.map(|path| path as *const DevicePath).unwrap_or(ptr::null());
+		Status((self.connect_controller)(controller, drivers_ptr, remaining_path, recursive))
+	}
+
+	// driver None disconnects all drivers, child None all children
+	pub fn disconnect_controller(&self, controller: Handle, driver: Option<Handle>, child: Option<Handle>) -> Status {
+		Status((self.disconnect_controller)(controller, driver.unwrap_or(Handle::null()), child.unwrap_or(Handle::null())))
+	}
+
+	// the firmware functions are variadic, which can't be declared with the efi calling convention,
+	// but since everything is passed as pointers a fixed number of arguments ends up the same way
+	pub unsafe fn install_multiple_protocol_interfaces(&self, handle: &mut Handle, interfaces: &[(Guid, *mut ())]) -> Status {
+		let f = self.install_multiple_protocol_interfaces;
+		let end = ptr::null::<()>();
+		let g = |i: usize| &interfaces[i].0 as *const Guid;
+		let p = |i: usize| interfaces[i].1;
+		match interfaces.len() {
+			1 => Status(transmute::<_, efi_fn!(*mut Handle, *const Guid, *mut (), *const ())>(f)(handle, g(0), p(0), end)),
+			2 => Status(transmute::<_, efi_fn!(*mut Handle, *const Guid, *mut (), *const Guid, *mut (), *const ())>(f)(handle, g(0), p(0), g(1), p(1), end)),
+			3 => Status(transmute::<_, efi_fn!(*mut Handle, *const Guid, *mut (), *const Guid, *mut (), *const Guid, *mut (), *const ())>(f)(handle, g(0), p(0), g(1), p(1), g(2), p(2), end)),
+			4 => Status(transmute::<_, efi_fn!(*mut Handle, *const Guid, *mut (), *const Guid, *mut (), *const Guid, *mut (), *const Guid, *mut (), *const ())>(f)(handle, g(0), p(0), g(1), p(1), g(2), p(2), g(3), p(3), end)),
+			_ => Status::InvalidParameter
 		}
-		unsafe {
-			&*((self.mem as *const u8).offset((index * self.descriptor_size) as isize) as *const MemoryDescriptor)
+	}
+
+	pub unsafe fn uninstall_multiple_protocol_interfaces(&self, handle: Handle, interfaces: &[(Guid, *mut ())]) -> Status {
+		let f = self.uninstall_multiple_protocol_interfaces;
+		let end = ptr::null::<()>();
+		let g = |i: usize| &interfaces[i].0 as *const Guid;
+		let p = |i: usize| interfaces[i].1;
+		match interfaces.len() {
+			1 => Status(transmute::<_, efi_fn!(Handle, *const Guid, *mut (), *const ())>(f)(handle, g(0), p(0), end)),
+			2 => Status(transmute::<_, efi_fn!(Handle, *const Guid, *mut (), *const Guid, *mut (), *const ())>(f)(handle, g(0), p(0), g(1), p(1), end)),
+			3 => Status(transmute::<_, efi_fn!(Handle, *const Guid, *mut (), *const Guid, *mut (), *const Guid, *mut (), *const ())>(f)(handle, g(0), p(0), g(1), p(1), g(2), p(2), end)),
+			4 => Status(transmute::<_, efi_fn!(Handle, *const Guid, *mut (), *const Guid, *mut (), *const Guid, *mut (), *const Guid, *mut (), *const ())>(f)(handle, g(0), p(0), g(1), p(1), g(2), p(2), g(3), p(3), end)),
+			_ => Status::InvalidParameter
 		}
 	}
 
-	pub fn get_descriptor_count(&self) -> usize {
-		self.size / self.descriptor_size
+	pub fn calculate_crc32(&self, data: &[u8]) -> ::Result<u32> {
+		let mut crc = 0;
+		try!(Status((self.calculate_crc32)(data.as_ptr(), data.len(), &mut crc)).into_result());
+		Ok(crc)
+	}
+
+	// the firmware's memmove and memset
+	pub unsafe fn copy_mem(&self, destination: *mut u8, source: *const u8, length: usize) {
+		(self.copy_mem)(destination, source, length)
 	}
 
-	pub fn iter<'b>(&'b self) -> MemoryMapIterator<'b> {
-		MemoryMapIterator {
-			map: self,
-			index: 0
+	pub unsafe fn set_mem(&self, buffer: *mut u8, length: usize, value: u8) {
+		(self.set_mem)(buffer, length, value)
+	}
+
+	// like create_event, except that the event joins the group with the given guid
+	pub unsafe fn create_event_ex(&self, typ: EventType, tpl: Tpl, notify: Option<EventNotify>, context: *mut (), group: Option<&Guid>) -> ::Result<*const ()> {
+		let mut event = ptr::null();
+		let group = group.map(|group| group as *const Guid).unwrap_or(ptr::null());
+		try!(Status((self.create_event_ex)(typ.bits(), tpl.0, notify, context, group, &mut event)).into_result());
+		Ok(event)
+	}
+
+	// fills buffer with the map, returning its size, key, descriptor size and descriptor version
+	pub unsafe fn get_memory_map(&self, buffer: *mut (), capacity: usize) -> ::Result<(usize, usize, usize, u32)> {
+		let mut size = capacity;
+		let mut key = 0;
+		let mut descriptor_size = 0;
+		let mut descriptor_version = 0;
+		try!(Status((self.get_memory_map)(&mut size, buffer, &mut key, &mut descriptor_size, &mut descriptor_version)).into_result());
+		Ok((size, key, descriptor_size, descriptor_version))
+	}
+
+	pub fn memory_map(&self) -> ::Result<MemoryMap> {
+		let mut size = 0;
+		let mut key = 0;
+		let mut descriptor_size = 0usize;
+		let mut descriptor_version = 0u32;
+		match Status((self.get_memory_map)(&mut size, ptr::null_mut(), &mut key, &mut descriptor_size, &mut descriptor_version)) {
+			Status::BufferTooSmall => { },
+			error => return Err(error)
+		}
+		loop {
+			size += 2 * descriptor_size; // the allocation may end up splitting a free range in two
+			let mem = match self.alloc(MemoryType::LoaderData, size) {
+				Some(mem) => mem,
+				None => return Err(Status::OutOfResources)
+			};
+			let mut map_size = size;
+			match Status((self.get_memory_map)(&mut map_size, mem, &mut key, &mut descriptor_size, &mut descriptor_version)) {
+				Status::Success => return Ok(unsafe { MemoryMap::from_raw(mem, size, map_size, key, descriptor_size, descriptor_version) }),
+				Status::BufferTooSmall => {
+					unsafe { self.free(mem); }
+					size = map_size;
+				},
+				error => {
+					unsafe { self.free(mem); }
+					return Err(error);
+				}
+			}
 		}
 	}
-}
 
-impl Drop for MemoryMap {
-	fn drop(&mut self) {
-		// TODO: free
+	pub unsafe fn exit_boot_services(&self, image: Handle, key: usize) -> Status {
+		let status = Status((self.exit_boot_services)(image, key));
+		if status == Status::Success {
+			::boot_services = ptr::null();
+		}
+		status
 	}
 }
 
-pub struct MemoryMapIterator<'a> {
-	map: &'a MemoryMap,
-	index: usize
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub enum AllocType {
+	AnyPages,
+	MaxAddress,
+	Address,
 }
 
-impl<'a> Iterator for MemoryMapIterator<'a> {
-	type Item = (*const (), usize, MemoryType);
+#[cfg(test)]
+mod tests {
+	use super::BootServices;
 
-	fn next(&mut self) -> Option<(*const (), usize, MemoryType)> {
-		if self.index >= self.map.get_descriptor_count() {
-			return None;
-		}
-		let descriptor = self.map.get_descriptor(self.index);
-		self.index += 1;
-		Some((descriptor.phys as *const (), descriptor.count as usize * 4096, match MemoryType::from_code(descriptor.typ) { Some(typ) => typ, None => MemoryType::Reserved }))
+	// one line per entry of EFI_BOOT_SERVICES, all pointer sized, so a swapped or missing entry shows up here
+	#[test]
+	fn layout() {
+		assert_offset!(BootServices, raise_tpl, 0, 0);
+		assert_offset!(BootServices, restore_tpl, 4, 8);
+		assert_offset!(BootServices, allocate_pages, 8, 16);
+		assert_offset!(BootServices, free_pages, 12, 24);
+		assert_offset!(BootServices, get_memory_map, 16, 32);
+		assert_offset!(BootServices, allocate_pool, 20, 40);
+		assert_offset!(BootServices, free_pool, 24, 48);
+		assert_offset!(BootServices, create_event, 28, 56);
+		assert_offset!(BootServices, set_timer, 32, 64);
+		assert_offset!(BootServices, wait_for_event, 36, 72);
+		assert_offset!(BootServices, signal_event, 40, 80);
+		assert_offset!(BootServices, close_event, 44, 88);
+		assert_offset!(BootServices, check_event, 48, 96);
+		assert_offset!(BootServices, install_protocol_interface, 52, 104);
+		assert_offset!(BootServices, reinstall_protocol_interface, 56, 112);
+		assert_offset!(BootServices, uninstall_protocol_interface, 60, 120);
+		assert_offset!(BootServices, handle_protocol, 64, 128);
+		assert_offset!(BootServices, reserved, 68, 136);
+		assert_offset!(BootServices, register_protocol_notify, 72, 144);
+		assert_offset!(BootServices, locate_handle, 76, 152);
+		assert_offset!(BootServices, locate_device_path, 80, 160);
+		assert_offset!(BootServices, install_configuration_table, 84, 168);
+		assert_offset!(BootServices, load_image, 88, 176);
+		assert_offset!(BootServices, start_image, 92, 184);
+		assert_offset!(BootServices, exit, 96, 192);
+		assert_offset!(BootServices, unload_image, 100, 200);
+		assert_offset!(BootServices, exit_boot_services, 104, 208);
+		assert_offset!(BootServices, get_next_monotonic_count, 108, 216);
+		assert_offset!(BootServices, stall, 112, 224);
+		assert_offset!(BootServices, set_watchdog_timer, 116, 232);
+		assert_offset!(BootServices, connect_controller, 120, 240);
+		assert_offset!(BootServices, disconnect_controller, 124, 248);
+		assert_offset!(BootServices, open_protocol, 128, 256);
+		assert_offset!(BootServices, close_protocol, 132, 264);
+		assert_offset!(BootServices, open_protocol_information, 136, 272);
+		assert_offset!(BootServices, protocols_per_handle, 140, 280);
+		assert_offset!(BootServices, locate_handle_buffer, 144, 288);
+		assert_offset!(BootServices, locate_protocol, 148, 296);
+		assert_offset!(BootServices, install_multiple_protocol_interfaces, 152, 304);
+		assert_offset!(BootServices, uninstall_multiple_protocol_interfaces, 156, 312);
+		assert_offset!(BootServices, calculate_crc32, 160, 320);
+		assert_offset!(BootServices, copy_mem, 164, 328);
+		assert_offset!(BootServices, set_mem, 168, 336);
+		assert_offset!(BootServices, create_event_ex, 172, 344);
 	}
 }
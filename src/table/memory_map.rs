@@ -0,0 +1,404 @@
+use core::prelude::*;
+use core::ptr;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+	Reserved,
+	LoaderCode,
+	LoaderData,
+	BootServicesCode,
+	BootServicesData,
+	RuntimeServicesCode,
+	RuntimeServicesData,
+	Conventional,
+	Unusable,
+	AcpiReclaimable,
+	AcpiMemoryNvs,
+	MemoryMappedIo,
+	MemoryMappedIoPortSpace,
+	PalCode,
+	PersistentMemory,
+	Unaccepted,
+	Oem(u32), // 0x70000000..0x7FFFFFFF
+	Os(u32), // 0x80000000..0xFFFFFFFF, loaders use these to mark their own allocations
+	Unknown(u32)
+}
+
+impl MemoryType {
+	pub fn from_code(code: u32) -> MemoryType {
+		match code {
+			0 => MemoryType::Reserved,
+			1 => MemoryType::LoaderCode,
+			2 => MemoryType::LoaderData,
+			3 => MemoryType::BootServicesCode,
+			4 => MemoryType::BootServicesData,
+			5 => MemoryType::RuntimeServicesCode,
+			6 => MemoryType::RuntimeServicesData,
+			7 => MemoryType::Conventional,
+			8 => MemoryType::Unusable,
+			9 => MemoryType::AcpiReclaimable,
+			10 => MemoryType::AcpiMemoryNvs,
+			11 => MemoryType::MemoryMappedIo,
+			12 => MemoryType::MemoryMappedIoPortSpace,
+			13 => MemoryType::PalCode,
+			14 => MemoryType::PersistentMemory,
+			15 => MemoryType::Unaccepted,
+			0x70000000...0x7FFFFFFF => MemoryType::Oem(code),
+			0x80000000...0xFFFFFFFF => MemoryType::Os(code),
+			_ => MemoryType::Unknown(code)
+		}
+	}
+
+	pub fn code(&self) -> u32 {
+		match *self {
+			MemoryType::Reserved => 0,
+			MemoryType::LoaderCode => 1,
+			MemoryType::LoaderData => 2,
+			MemoryType::BootServicesCode => 3,
+			MemoryType::BootServicesData => 4,
+			MemoryType::RuntimeServicesCode => 5,
+			MemoryType::RuntimeServicesData => 6,
+			MemoryType::Conventional => 7,
+			MemoryType::Unusable => 8,
+			MemoryType::AcpiReclaimable => 9,
+			MemoryType::AcpiMemoryNvs => 10,
+			MemoryType::MemoryMappedIo => 11,
+			MemoryType::MemoryMappedIoPortSpace => 12,
+			MemoryType::PalCode => 13,
+			MemoryType::PersistentMemory => 14,
+			MemoryType::Unaccepted => 15,
+			MemoryType::Oem(code) | MemoryType::Os(code) | MemoryType::Unknown(code) => code
+		}
+	}
+}
+
+efi_flags!(MemoryAttribute: u64 {
+	MEMORY_UC = 0x1,
+	MEMORY_WC = 0x2,
+	MEMORY_WT = 0x4,
+	MEMORY_WB = 0x8,
+	MEMORY_UCE = 0x10,
+	MEMORY_WP = 0x1000,
+	MEMORY_RP = 0x2000,
+	MEMORY_XP = 0x4000,
+	MEMORY_NV = 0x8000,
+	MEMORY_MORE_RELIABLE = 0x10000,
+	MEMORY_RO = 0x20000,
+	MEMORY_SP = 0x40000,
+	MEMORY_CPU_CRYPTO = 0x80000,
+	MEMORY_ISA_MASK = 0x0FFFF00000000000,
+	MEMORY_ISA_VALID = 0x4000000000000000,
+	MEMORY_RUNTIME = 0x8000000000000000,
+});
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryDescriptor {
+	pub typ: u32,
+	pub pad: u32,
+	pub phys: u64,
+	pub virt: u64,
+	pub count: u64,
+	pub attribute: u64
+}
+
+impl MemoryDescriptor {
+	pub fn memory_type(&self) -> MemoryType {
+		MemoryType::from_code(self.typ)
+	}
+
+	pub fn attributes(&self) -> MemoryAttribute {
+		MemoryAttribute::from_bits(self.attribute)
+	}
+
+	pub fn size(&self) -> u64 {
+		self.count * PAGE_SIZE
+	}
+
+	pub fn end(&self) -> u64 {
+		self.phys + self.size()
+	}
+
+	fn can_merge(&self, next: &MemoryDescriptor) -> bool {
+		// virtual addresses are all zero until SetVirtualAddressMap, after that they have to line up too
+		self.typ == next.typ && self.attribute == next.attribute && self.end() == next.phys
+			&& ((self.virt == 0 && next.virt == 0) || self.virt + self.size() == next.virt)
+	}
+}
+
+// owns the pool buffer the firmware wrote the map into
+pub struct MemoryMap {
+	mem: *mut (),
+	capacity: usize,
+	size: usize,
+	key: usize,
+	descriptor_size: usize,
+	descriptor_version: u32
+}
+
+impl MemoryMap {
+	pub unsafe fn from_raw(mem: *mut (), capacity: usize, size: usize, key: usize, descriptor_size: usize, descriptor_version: u32) -> MemoryMap {
+		MemoryMap {
+			mem: mem,
+			capacity: capacity,
+			size: size,
+			key: key,
+			descriptor_size: descriptor_size,
+			descriptor_version: descriptor_version
+		}
+	}
+
+	// fetches the map again into the same buffer, this is what you do when exit_boot_services fails
+	// because the map changed, since allocating is not allowed anymore at that point
+	pub fn refresh(&mut self) -> ::Result<()> {
+		let (size, key, descriptor_size, descriptor_version) = try!(unsafe { ::get_boot_services().get_memory_map(self.mem, self.capacity) });
+		self.size = size;
+		self.key = key;
+		self.descriptor_size = descriptor_size;
+		self.descriptor_version = descriptor_version;
+		Ok(())
+	}
+
+	pub fn key(&self) -> usize {
+		self.key
+	}
+
+	pub fn descriptor_version(&self) -> u32 {
+		self.descriptor_version
+	}
+
+	// the firmware is allowed to use a larger stride than size_of::<MemoryDescriptor>()
+	pub fn descriptor_size(&self) -> usize {
+		self.descriptor_size
+	}
+
+	pub fn as_ptr(&self) -> *const () {
+		self.mem
+	}
+
+	pub fn byte_size(&self) -> usize {
+		self.size
+	}
+
+	fn descriptor_ptr(&self, index: usize) -> *mut u8 {
+		if index * self.descriptor_size >= self.size {
+			panic!()
+		}
+		unsafe {
+			(self.mem as *mut u8).offset((index * self.descriptor_size) as isize)
+		}
+	}
+
+	pub fn get_descriptor(&self, index: usize) -> &MemoryDescriptor {
+		unsafe {
+			&*(self.descriptor_ptr(index) as *const MemoryDescriptor)
+		}
+	}
+
+	pub fn get_descriptor_mut(&mut self, index: usize) -> &mut MemoryDescriptor {
+		unsafe {
+			&mut *(self.descriptor_ptr(index) as *mut MemoryDescriptor)
+		}
+	}
+
+	pub fn get_descriptor_count(&self) -> usize {
+		self.size / self.descriptor_size
+	}
+
+	pub fn iter<'b>(&'b self) -> MemoryMapIterator<'b> {
+		MemoryMapIterator {
+			map: self,
+			index: 0
+		}
+	}
+
+	// sorts by physical address, the firmware doesn't guarantee any order
+	pub fn sort(&mut self) {
+		for i in 1..self.get_descriptor_count() {
+			let mut j = i;
+			while j > 0 && self.get_descriptor(j - 1).phys > self.get_descriptor(j).phys {
+				self.swap(j - 1, j);
+				j -= 1;
+			}
+		}
+	}
+
+	// merges physically adjacent ranges with the same type and attributes, this also sorts the map
+	pub fn coalesce(&mut self) {
+		self.sort();
+		let count = self.get_descriptor_count();
+		if count == 0 {
+			return;
+		}
+		let mut last = 0;
+		for i in 1..count {
+			let current = *self.get_descriptor(i);
+			let merged = {
+				let previous = self.get_descriptor_mut(last);
+				if previous.can_merge(&current) {
+					previous.count += current.count;
+					true
+				} else {
+					false
+				}
+			};
+			if !merged {
+				last += 1;
+				if last != i {
+					unsafe {
+						ptr::copy(self.descriptor_ptr(i), self.descriptor_ptr(last), self.descriptor_size);
+					}
+				}
+			}
+		}
+		self.size = (last + 1) * self.descriptor_size;
+	}
+
+	pub fn total_pages(&self, typ: MemoryType) -> u64 {
+		self.iter().filter(|descriptor| descriptor.memory_type() == typ).fold(0, |total, descriptor| total + descriptor.count)
+	}
+
+	pub fn total_size(&self, typ: MemoryType) -> u64 {
+		self.total_pages(typ) * PAGE_SIZE
+	}
+
+	fn swap(&mut self, a: usize, b: usize) {
+		let a = self.descriptor_ptr(a);
+		let b = self.descriptor_ptr(b);
+		unsafe {
+			for i in 0..self.descriptor_size as isize {
+				ptr::swap(a.offset(i), b.offset(i));
+			}
+		}
+	}
+}
+
+// for tests, wraps descriptors laid out by hand. boot services aren't active there, so dropping the
+// map leaves the blob alone
+#[cfg(test)]
+impl MemoryMap {
+	pub fn from_blob(blob: &mut [u64], descriptor_size: usize) -> MemoryMap {
+		let size = blob.len() * 8;
+		unsafe { MemoryMap::from_raw(blob.as_mut_ptr() as *mut (), size, size, 0, descriptor_size, 1) }
+	}
+}
+
+impl Drop for MemoryMap {
+	fn drop(&mut self) {
+		// the buffer is LoaderData, so once boot services are gone it simply stays around for the kernel
+		if ::boot_services_active() {
+			unsafe {
+				::get_boot_services().free(self.mem);
+			}
+		}
+	}
+}
+
+pub struct MemoryMapIterator<'a> {
+	map: &'a MemoryMap,
+	index: usize
+}
+
+impl<'a> Iterator for MemoryMapIterator<'a> {
+	type Item = &'a MemoryDescriptor;
+
+	fn next(&mut self) -> Option<&'a MemoryDescriptor> {
+		if self.index >= self.map.get_descriptor_count() {
+			return None;
+		}
+		let descriptor = self.map.get_descriptor(self.index);
+		self.index += 1;
+		Some(descriptor)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use core::prelude::*;
+	use collections::Vec;
+
+	use super::{MemoryDescriptor, MemoryMap, MemoryType, PAGE_SIZE};
+
+	// 48 byte stride, the last word of each descriptor is padding and holds the index the
+	// descriptor should end up at, so moving it around has to carry the whole stride along
+	const UNSORTED: [u64; 5 * 6] = [
+		7, 0x300000, 0, 0x100, 0xF, 3, // conventional
+		2, 0x100000, 0, 0x100, 0xF, 1, // loader data
+		7, 0x000000, 0, 0x100, 0xF, 0, // conventional
+		4, 0x400000, 0, 0x20, 0xF, 4, // boot services data
+		7, 0x200000, 0, 0x100, 0xF, 2 // conventional, runs into the first one
+	];
+
+	// all conventional and physically contiguous, only attributes and virtual addresses differ
+	const ADJACENT: [u64; 6 * 6] = [
+		7, 0x00000, 0, 0x10, 0xF, 0,
+		7, 0x10000, 0, 0x10, 0xF, 0, // merges into the one above
+		7, 0x20000, 0, 0x10, 0x8, 0, // different attributes
+		7, 0x30000, 0x80030000, 0x10, 0x8, 0, // mapped while the one before isn't
+		7, 0x40000, 0x80040000, 0x10, 0x8, 0, // virtual addresses line up, merges
+		7, 0x50000, 0x90000000, 0x10, 0x8, 0 // virtual addresses don't line up
+	];
+
+	fn ranges(map: &MemoryMap) -> Vec<(u64, u64)> {
+		map.iter().map(|descriptor| (descriptor.phys, descriptor.count)).collect()
+	}
+
+	#[test]
+	fn sort_by_address() {
+		let mut blob = UNSORTED;
+		let mut map = MemoryMap::from_blob(&mut blob, 48);
+		map.sort();
+		assert_eq!(ranges(&map), vec![(0x000000, 0x100), (0x100000, 0x100), (0x200000, 0x100), (0x300000, 0x100), (0x400000, 0x20)]);
+		assert_eq!(map.get_descriptor(1).memory_type(), MemoryType::LoaderData);
+		for i in 0..5 {
+			assert_eq!(blob[i * 6 + 5], i as u64);
+		}
+	}
+
+	#[test]
+	fn coalesce_same_type() {
+		let mut blob = UNSORTED;
+		let mut map = MemoryMap::from_blob(&mut blob, 48);
+		map.coalesce();
+		assert_eq!(ranges(&map), vec![(0x000000, 0x100), (0x100000, 0x100), (0x200000, 0x200), (0x400000, 0x20)]);
+		assert_eq!(map.get_descriptor_count(), 4);
+		assert_eq!(map.byte_size(), 4 * 48);
+	}
+
+	#[test]
+	fn coalesce_respects_attributes_and_virtual_addresses() {
+		let mut blob = ADJACENT;
+		let mut map = MemoryMap::from_blob(&mut blob, 48);
+		map.coalesce();
+		assert_eq!(ranges(&map), vec![(0x00000, 0x20), (0x20000, 0x10), (0x30000, 0x20), (0x50000, 0x10)]);
+		assert_eq!(map.get_descriptor(2).virt, 0x80030000);
+		assert_eq!(map.get_descriptor(3).virt, 0x90000000);
+	}
+
+	#[test]
+	fn totals_per_type() {
+		let mut blob = UNSORTED;
+		let mut map = MemoryMap::from_blob(&mut blob, 48);
+		assert_eq!(map.total_pages(MemoryType::Conventional), 0x300);
+		assert_eq!(map.total_size(MemoryType::Conventional), 0x300 * PAGE_SIZE);
+		assert_eq!(map.total_size(MemoryType::LoaderData), 0x100 * PAGE_SIZE);
+		assert_eq!(map.total_size(MemoryType::BootServicesData), 0x20 * PAGE_SIZE);
+		assert_eq!(map.total_size(MemoryType::Reserved), 0);
+		// merging doesn't change how much there is of anything
+		map.coalesce();
+		assert_eq!(map.total_size(MemoryType::Conventional), 0x300 * PAGE_SIZE);
+		assert_eq!(map.total_size(MemoryType::BootServicesData), 0x20 * PAGE_SIZE);
+	}
+
+	#[test]
+	fn layout() {
+		assert_offset!(MemoryDescriptor, typ, 0, 0);
+		assert_offset!(MemoryDescriptor, pad, 4, 4);
+		assert_offset!(MemoryDescriptor, phys, 8, 8);
+		assert_offset!(MemoryDescriptor, virt, 16, 16);
+		assert_offset!(MemoryDescriptor, count, 24, 24);
+		assert_offset!(MemoryDescriptor, attribute, 32, 32);
+	}
+}
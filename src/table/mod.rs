@@ -1,12 +1,14 @@
 mod system;
 mod boot_services;
 mod runtime_services;
+mod memory_map;
 
 use core::ops::{Deref, DerefMut};
 
 pub use self::system::*;
 pub use self::boot_services::*;
 pub use self::runtime_services::*;
+pub use self::memory_map::*;
 
 #[repr(C)]
 pub struct Table<T> {
@@ -32,3 +34,31 @@ impl<T> DerefMut for Table<T> {
 		&mut self.inner
 	}
 }
+
+#[allow(dead_code)]
+fn assert_layout() {
+	assert_size!(Table<()>, 24, 24);
+	assert_size!(Table<System<'static>>, 72, 120);
+	assert_size!(ConfigEntry, 20, 24);
+	assert_size!(Table<BootServices>, 24 + 44 * 4, 24 + 44 * 8); // 44 function pointers, so no room for gaps
+	assert_size!(OpenProtocolInformation, 16, 24);
+	assert_size!(MemoryDescriptor, 40, 40);
+	assert_size!(Table<RuntimeServices>, 24 + 14 * 4, 24 + 14 * 8);
+	assert_size!(TimeCapabilities, 12, 12);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Table, BootServices, RuntimeServices};
+
+	#[test]
+	fn layout() {
+		assert_offset!(Table<()>, signature, 0, 0);
+		assert_offset!(Table<()>, revision, 8, 8);
+		assert_offset!(Table<()>, size, 12, 12);
+		assert_offset!(Table<()>, crc32, 16, 16);
+		assert_offset!(Table<()>, reserved, 20, 20);
+		assert_offset!(Table<BootServices>, inner, 24, 24);
+		assert_offset!(Table<RuntimeServices>, inner, 24, 24);
+	}
+}
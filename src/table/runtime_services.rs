@@ -1,2 +1,173 @@
+use core::prelude::*;
+use core::mem::zeroed;
+use core::ptr;
+
+use ::{Status, Guid, Time};
+use table::MemoryDescriptor;
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetType {
+	Cold = 0,
+	Warm = 1,
+	Shutdown = 2,
+	PlatformSpecific = 3 // the reset data names the reset by guid, see reset::platform_reset_data
+}
+
+// field order follows EFI_RUNTIME_SERVICES as of UEFI 2.0
 #[repr(C)]
-pub struct RuntimeServices;
+pub struct RuntimeServices {
+	get_time: efi_fn!(*mut Time, *mut TimeCapabilities),
+	set_time: efi_fn!(*const Time),
+	get_wakeup_time: efi_fn!(*mut u8, *mut u8, *mut Time), // BOOLEANs the firmware writes, which may be other than 0 or 1
+	set_wakeup_time: efi_fn!(bool, *const Time),
+
+	set_virtual_address_map: efi_fn!(usize, usize, u32, *mut MemoryDescriptor),
+	convert_pointer: efi_fn!(usize, *mut *const ()),
+
+	get_variable: efi_fn!(*const u16, *const Guid, *mut u32, *mut usize, *mut u8),
+	get_next_variable_name: efi_fn!(*mut usize, *mut u16, *mut Guid),
+	set_variable: efi_fn!(*const u16, *const Guid, u32, usize, *const u8),
+
+	get_next_high_monotonic_count: efi_fn!(*mut u32),
+	reset_system: efi_fn!((ResetType, usize, usize, *const u8) -> ()),
+
+	update_capsule: efi_fn!(*const *const (), usize, u64),
+	query_capsule_capabilities: efi_fn!(*const *const (), usize, *mut u64, *mut u32),
+
+	query_variable_info: efi_fn!(u32, *mut u64, *mut u64, *mut u64),
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TimeCapabilities {
+	pub resolution: u32, // in counts per second, 1 for a normal PC-AT CMOS RTC
+	pub accuracy: u32, // in parts per million times 1e6, so 50 ppm is 50000000
+	sets_to_zero: u8 // a BOOLEAN, see sets_to_zero()
+}
+
+impl TimeCapabilities {
+	// setting the time clears everything below the resolution
+	pub fn sets_to_zero(&self) -> bool {
+		self.sets_to_zero != 0
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VariableStorageInfo {
+	pub maximum_storage_size: u64,
+	pub remaining_storage_size: u64,
+	pub maximum_variable_size: u64
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WakeupTime {
+	pub enabled: bool,
+	pub pending: bool,
+	pub time: Time
+}
+
+impl RuntimeServices {
+	pub fn get_time(&self) -> ::Result<(Time, TimeCapabilities)> {
+		let mut time: Time = unsafe { zeroed() };
+		let mut capabilities: TimeCapabilities = unsafe { zeroed() };
+		try!(Status((self.get_time)(&mut time, &mut capabilities)).into_result());
+		Ok((time, capabilities))
+	}
+
+	pub fn set_time(&self, time: &Time) -> ::Result<()> {
+		Status((self.set_time)(time)).into_result()
+	}
+
+	// fails with Unsupported on the many platforms without a wakeup alarm
+	pub fn get_wakeup_time(&self) -> ::Result<WakeupTime> {
+		let mut enabled = 0;
+		let mut pending = 0;
+		let mut time: Time = unsafe { zeroed() };
+		try!(Status((self.get_wakeup_time)(&mut enabled, &mut pending, &mut time)).into_result());
+		Ok(WakeupTime {
+			enabled: enabled != 0,
+			pending: pending != 0,
+			time: time
+		})
+	}
+
+	// None disables the alarm
+	pub fn set_wakeup_time(&self, time: Option<&Time>) -> ::Result<()> {
+		let status = match time {
+			Some(time) => Status((self.set_wakeup_time)(true, time)),
+			None => Status((self.set_wakeup_time)(false, ptr::null()))
+		};
+		status.into_result()
+	}
+
+	// only callable once, after exit_boot_services, with the runtime descriptors given their virtual addresses
+	pub unsafe fn set_virtual_address_map(&self, map_size: usize, descriptor_size: usize, descriptor_version: u32, map: *mut MemoryDescriptor) -> Status {
+		Status((self.set_virtual_address_map)(map_size, descriptor_size, descriptor_version, map))
+	}
+
+	// only valid from a virtual address change notify function, debug_disposition 1 allows null pointers
+	pub unsafe fn convert_pointer(&self, debug_disposition: usize, address: *mut *const ()) -> Status {
+		Status((self.convert_pointer)(debug_disposition, address))
+	}
+
+	// name is null terminated, size is updated to what the variable needs on BufferTooSmall
+	pub unsafe fn get_variable(&self, name: *const u16, vendor: &Guid, attributes: &mut u32, size: &mut usize, data: *mut u8) -> Status {
+		Status((self.get_variable)(name, vendor, attributes, size, data))
+	}
+
+	// name and vendor hold the previous variable on the way in (an empty name to start) and the next one
+	// on the way out, size is the size of the name buffer in bytes
+	pub unsafe fn get_next_variable_name(&self, size: &mut usize, name: *mut u16, vendor: &mut Guid) -> Status {
+		Status((self.get_next_variable_name)(size, name, vendor))
+	}
+
+	pub unsafe fn set_variable(&self, name: *const u16, vendor: &Guid, attributes: u32, size: usize, data: *const u8) -> Status {
+		Status((self.set_variable)(name, vendor, attributes, size, data))
+	}
+
+	// for the variables with the given attributes
+	pub fn query_variable_info(&self, attributes: u32) -> ::Result<VariableStorageInfo> {
+		let mut info = VariableStorageInfo {
+			maximum_storage_size: 0,
+			remaining_storage_size: 0,
+			maximum_variable_size: 0
+		};
+		try!(Status((self.query_variable_info)(attributes, &mut info.maximum_storage_size, &mut info.remaining_storage_size, &mut info.maximum_variable_size)).into_result());
+		Ok(info)
+	}
+
+	// data is an optional null terminated string describing the reason, optionally followed by binary data
+	pub unsafe fn reset_system(&self, typ: ResetType, status: Status, size: usize, data: *const u8) -> ! {
+		(self.reset_system)(typ, status.0, size, data);
+		// the firmware never returns from this
+		loop { }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{RuntimeServices, TimeCapabilities};
+
+	#[test]
+	fn layout() {
+		assert_offset!(RuntimeServices, get_time, 0, 0);
+		assert_offset!(RuntimeServices, set_time, 4, 8);
+		assert_offset!(RuntimeServices, get_wakeup_time, 8, 16);
+		assert_offset!(RuntimeServices, set_wakeup_time, 12, 24);
+		assert_offset!(RuntimeServices, set_virtual_address_map, 16, 32);
+		assert_offset!(RuntimeServices, convert_pointer, 20, 40);
+		assert_offset!(RuntimeServices, get_variable, 24, 48);
+		assert_offset!(RuntimeServices, get_next_variable_name, 28, 56);
+		assert_offset!(RuntimeServices, set_variable, 32, 64);
+		assert_offset!(RuntimeServices, get_next_high_monotonic_count, 36, 72);
+		assert_offset!(RuntimeServices, reset_system, 40, 80);
+		assert_offset!(RuntimeServices, update_capsule, 44, 88);
+		assert_offset!(RuntimeServices, query_capsule_capabilities, 48, 96);
+		assert_offset!(RuntimeServices, query_variable_info, 52, 104);
+
+		assert_offset!(TimeCapabilities, resolution, 0, 0);
+		assert_offset!(TimeCapabilities, accuracy, 4, 4);
+		assert_offset!(TimeCapabilities, sets_to_zero, 8, 8);
+	}
+}
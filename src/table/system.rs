@@ -1,4 +1,5 @@
 use table::{BootServices, RuntimeServices};
+use core::prelude::*;
 use core::slice;
 use ::{Table, Handle, Guid};
 use protocol;
@@ -28,6 +29,16 @@ pub struct ConfigEntry  {
 	pub ptr: *const ()
 }
 
+// a type that lives in the configuration table under its guid
+pub trait ConfigTable {
+	fn guid() -> Guid;
+}
+
+pub const ACPI_TABLE_GUID: Guid = Guid(0xEB9D2D30, 0x2D88, 0x11D3, 0x9A, 0x16, 0x00, 0x90, 0x27, 0x3F, 0xC1, 0x4D);
+pub const ACPI_20_TABLE_GUID: Guid = Guid(0x8868E871, 0xE4F1, 0x11D3, 0xBC, 0x22, 0x00, 0x80, 0xC7, 0x3C, 0x88, 0x81);
+pub const SMBIOS_TABLE_GUID: Guid = Guid(0xEB9D2D31, 0x2D88, 0x11D3, 0x9A, 0x16, 0x00, 0x90, 0x27, 0x3F, 0xC1, 0x4D);
+pub const SMBIOS3_TABLE_GUID: Guid = Guid(0xF2FD1544, 0x9794, 0x4A2C, 0x99, 0x2E, 0xE5, 0xBB, 0xCF, 0x20, 0xE3, 0x94);
+
 impl<'a> System<'a> {
 	pub fn get_stdin(&self) -> &protocol::SimpleTextInput {
 		&*self.console_in
@@ -54,4 +65,37 @@ impl<'a> System<'a> {
 			slice::from_raw_parts(self.config_table, self.config_count)
 		}
 	}
+
+	pub fn find_config_table_raw(&self, guid: &Guid) -> Option<*const ()> {
+		self.get_config_table().iter().find(|entry| entry.guid == *guid).map(|entry| entry.ptr)
+	}
+
+	pub fn find_config_table<T: ConfigTable>(&self) -> Option<&T> {
+		self.find_config_table_raw(&<T as ConfigTable>::guid()).map(|ptr| unsafe { &*(ptr as *const T) })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{System, ConfigEntry};
+
+	#[test]
+	fn layout() {
+		// firmware_revision is a u32, but the handle after it is pointer aligned
+		assert_offset!(System<'static>, firmware_vendor, 0, 0);
+		assert_offset!(System<'static>, firmware_revision, 4, 8);
+		assert_offset!(System<'static>, console_in_handle, 8, 16);
+		assert_offset!(System<'static>, console_in, 12, 24);
+		assert_offset!(System<'static>, console_out_handle, 16, 32);
+		assert_offset!(System<'static>, console_out, 20, 40);
+		assert_offset!(System<'static>, standard_error_handle, 24, 48);
+		assert_offset!(System<'static>, standard_error, 28, 56);
+		assert_offset!(System<'static>, runtime_services, 32, 64);
+		assert_offset!(System<'static>, boot_services, 36, 72);
+		assert_offset!(System<'static>, config_count, 40, 80);
+		assert_offset!(System<'static>, config_table, 44, 88);
+
+		assert_offset!(ConfigEntry, guid, 0, 0);
+		assert_offset!(ConfigEntry, ptr, 16, 16);
+	}
 }
@@ -0,0 +1,83 @@
+use core::prelude::*;
+use core::marker::PhantomData;
+
+use table::Tpl;
+
+// raises the task priority level for as long as it lives, which keeps notify functions at or
+// below that level from running, so it works as a critical section against them. guards have to be
+// dropped in the reverse order they were made, nested() ties an inner guard to its outer one so the
+// borrow checker enforces that
+pub struct TplGuard<'a> {
+	old: Tpl,
+	tpl: Tpl,
+	_outer: PhantomData<&'a ()>
+}
+
+impl TplGuard<'static> {
+	// panics if tpl is Application (that can only ever be restored to) or below the current level
+	pub fn raise(tpl: Tpl) -> TplGuard<'static> {
+		TplGuard::new(tpl)
+	}
+}
+
+impl<'a> TplGuard<'a> {
+	fn new<'b>(tpl: Tpl) -> TplGuard<'b> {
+		if tpl == Tpl::Application {
+			panic!("cannot raise the tpl to Application");
+		}
+		let old = unsafe { ::get_boot_services().raise_tpl(tpl) };
+		if old > tpl {
+			unsafe {
+				::get_boot_services().restore_tpl(old);
+			}
+			panic!("cannot raise the tpl from {:?} to {:?}", old, tpl);
+		}
+		TplGuard {
+			old: old,
+			tpl: tpl,
+			_outer: PhantomData
+		}
+	}
+
+	// a further raise that can't outlive this guard
+	pub fn nested<'b>(&'b self, tpl: Tpl) -> TplGuard<'b> {
+		TplGuard::new(tpl)
+	}
+
+	pub fn previous(&self) -> Tpl {
+		self.old
+	}
+}
+
+impl<'a> Drop for TplGuard<'a> {
+	fn drop(&mut self) {
+		unsafe {
+			let boot_services = ::get_boot_services();
+			// raising to HighLevel doesn't dispatch anything and tells us where we are
+			let now = boot_services.raise_tpl(Tpl::HighLevel);
+			debug_assert!(now == self.tpl, "tpl guards dropped out of order");
+			// restoring may never go up, so if an outer guard already went below us stay there
+			if now < self.old {
+				boot_services.restore_tpl(now);
+			} else {
+				boot_services.restore_tpl(self.old);
+			}
+		}
+	}
+}
+
+// the firmware has no getter for this, so raise to the highest level and look at what we came from.
+// restoring runs any notify functions that became pending in between
+pub fn current() -> Tpl {
+	unsafe {
+		let boot_services = ::get_boot_services();
+		let tpl = boot_services.raise_tpl(Tpl::HighLevel);
+		boot_services.restore_tpl(tpl);
+		tpl
+	}
+}
+
+pub fn critical_section<T, F: FnOnce() -> T>(tpl: Tpl, f: F) -> T {
+	let _guard = TplGuard::raise(tpl);
+	f()
+}
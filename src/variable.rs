@@ -0,0 +1,186 @@
+use core::prelude::*;
+use core::ptr;
+use collections::{Vec, String};
+
+use table::VariableStorageInfo;
+use ::{Status, Guid};
+
+efi_flags!(VariableAttributes: u32 {
+	VARIABLE_NON_VOLATILE = 0x01,
+	VARIABLE_BOOTSERVICE_ACCESS = 0x02,
+	VARIABLE_RUNTIME_ACCESS = 0x04,
+	VARIABLE_HARDWARE_ERROR_RECORD = 0x08,
+	VARIABLE_AUTHENTICATED_WRITE_ACCESS = 0x10, // deprecated
+	VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS = 0x20,
+	VARIABLE_APPEND_WRITE = 0x40,
+	VARIABLE_ENHANCED_AUTHENTICATED_ACCESS = 0x80,
+});
+
+// the vendor guid of the variables the spec defines (BootOrder, Boot####, OsIndications...)
+pub const GLOBAL_VARIABLE: Guid = Guid(0x8BE4DF61, 0x93CA, 0x11D2, 0xAA, 0x0D, 0x00, 0xE0, 0x98, 0x03, 0x2B, 0x8C);
+
+// everything here goes through the runtime services, so it keeps working after exit_boot_services.
+// the exceptions are the functions returning a Vec or String, those need the boot services allocator
+
+// variable names get converted on the stack so there's no allocation involved
+const MAX_NAME_LENGTH: usize = 256;
+
+struct Name {
+	buffer: [u16; MAX_NAME_LENGTH]
+}
+
+impl Name {
+	fn new(name: &str) -> ::Result<Name> {
+		let mut buffer = [0; MAX_NAME_LENGTH];
+		let mut cursor = 0;
+		for c in name.chars() {
+			// always leave room for the terminator
+			if cursor + 2 >= MAX_NAME_LENGTH {
+				return Err(Status::InvalidParameter);
+			}
+			cursor += c.encode_utf16(&mut buffer[cursor..]).unwrap();
+		}
+		buffer[cursor] = 0;
+		Ok(Name {
+			buffer: buffer
+		})
+	}
+
+	fn as_ptr(&self) -> *const u16 {
+		self.buffer.as_ptr()
+	}
+}
+
+// reads the variable into buffer, returning its size and attributes. fails with BufferTooSmall if it
+// doesn't fit, size() says how much room it needs
+pub fn get(name: &str, vendor: &Guid, buffer: &mut [u8]) -> ::Result<(usize, VariableAttributes)> {
+	let name = try!(Name::new(name));
+	let mut attributes = 0;
+	let mut size = buffer.len();
+	try!(unsafe { ::get_runtime_services().get_variable(name.as_ptr(), vendor, &mut attributes, &mut size, buffer.as_mut_ptr()) }.into_result());
+	Ok((size, VariableAttributes::from_bits(attributes)))
+}
+
+pub fn size(name: &str, vendor: &Guid) -> ::Result<usize> {
+	let name = try!(Name::new(name));
+	let mut attributes = 0;
+	let mut size = 0;
+	match unsafe { ::get_runtime_services().get_variable(name.as_ptr(), vendor, &mut attributes, &mut size, ptr::null_mut()) } {
+		Status::BufferTooSmall | Status::Success => Ok(size),
+		error => Err(error)
+	}
+}
+
+// sizes the buffer itself, needs boot services
+pub fn get_vec(name: &str, vendor: &Guid) -> ::Result<(Vec<u8>, VariableAttributes)> {
+	let name = try!(Name::new(name));
+	let mut data = Vec::new();
+	loop {
+		let mut attributes = 0;
+		let mut size = data.capacity();
+		match unsafe { ::get_runtime_services().get_variable(name.as_ptr(), vendor, &mut attributes, &mut size, data.as_mut_ptr()) } {
+			Status::Success => {
+				unsafe { data.set_len(size); }
+				return Ok((data, VariableAttributes::from_bits(attributes)));
+			},
+			Status::BufferTooSmall => {
+				// the variable may have grown in between, so this just goes around again
+				data = Vec::with_capacity(size);
+			},
+			error => return Err(error)
+		}
+	}
+}
+
+// non-volatile variables also need BOOTSERVICE_ACCESS, and RUNTIME_ACCESS to be visible to the os
+pub fn set(name: &str, vendor: &Guid, attributes: VariableAttributes, data: &[u8]) -> ::Result<()> {
+	let name = try!(Name::new(name));
+	unsafe { ::get_runtime_services().set_variable(name.as_ptr(), vendor, attributes.bits(), data.len(), data.as_ptr()) }.into_result()
+}
+
+pub fn append(name: &str, vendor: &Guid, attributes: VariableAttributes, data: &[u8]) -> ::Result<()> {
+	set(name, vendor, attributes | VARIABLE_APPEND_WRITE, data)
+}
+
+pub fn delete(name: &str, vendor: &Guid) -> ::Result<()> {
+	let name = try!(Name::new(name));
+	unsafe { ::get_runtime_services().set_variable(name.as_ptr(), vendor, 0, 0, ptr::null()) }.into_result()
+}
+
+// how much room there is for variables with the given attributes
+pub fn query_info(attributes: VariableAttributes) -> ::Result<VariableStorageInfo> {
+	::get_runtime_services().query_variable_info(attributes.bits())
+}
+
+pub enum NextName {
+	Found,
+	Done,
+	// the next name needs this many u16s including the terminator, name and vendor are unchanged so
+	// the call can be repeated with a bigger buffer holding the same previous name
+	BufferTooSmall(usize)
+}
+
+// the allocation free way to enumerate: name holds the previous name (start with an empty one) and
+// gets the next one written into it
+pub fn next_name(name: &mut [u16], vendor: &mut Guid) -> ::Result<NextName> {
+	let mut size = name.len() * 2;
+	match unsafe { ::get_runtime_services().get_next_variable_name(&mut size, name.as_mut_ptr(), vendor) } {
+		Status::Success => Ok(NextName::Found),
+		Status::NotFound => Ok(NextName::Done),
+		Status::BufferTooSmall => Ok(NextName::BufferTooSmall((size + 1) / 2)),
+		error => Err(error)
+	}
+}
+
+// every variable's name and vendor guid, needs boot services
+pub fn names() -> VariableNames {
+	VariableNames {
+		name: vec![0; 64],
+		vendor: Guid(0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0),
+		done: false
+	}
+}
+
+pub struct VariableNames {
+	name: Vec<u16>,
+	vendor: Guid,
+	done: bool
+}
+
+impl Iterator for VariableNames {
+	type Item = ::Result<(String, Guid)>;
+
+	fn next(&mut self) -> Option<::Result<(String, Guid)>> {
+		if self.done {
+			return None;
+		}
+		loop {
+			let mut size = self.name.len() * 2;
+			match unsafe { ::get_runtime_services().get_next_variable_name(&mut size, self.name.as_mut_ptr(), &mut self.vendor) } {
+				Status::Success => {
+					let length = self.name.iter().position(|c| *c == 0).unwrap_or(self.name.len());
+					return Some(Ok((String::from_utf16_lossy(&self.name[..length]), self.vendor)));
+				},
+				Status::BufferTooSmall => {
+					// firmware that asks for no more than it already got would have us loop forever
+					let wanted = (size + 1) / 2;
+					if wanted <= self.name.len() {
+						self.done = true;
+						return Some(Err(Status::BufferTooSmall));
+					}
+					// the previous name has to stay in the buffer, so grow it in place
+					let extra = wanted - self.name.len();
+					self.name.extend((0..extra).map(|_| 0));
+				},
+				Status::NotFound => {
+					self.done = true;
+					return None;
+				},
+				error => {
+					self.done = true;
+					return Some(Err(error));
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,34 @@
+use core::prelude::*;
+use core::ptr;
+
+// the firmware arms the watchdog with this many seconds before it starts an image
+pub const DEFAULT_TIMEOUT: usize = 300;
+
+pub fn disable() -> ::Result<()> {
+	unsafe { ::get_boot_services().set_watchdog_timer(0, 0, 0, ptr::null()) }.into_result()
+}
+
+// timeout is in seconds, codes up to 0xFFFF belong to the firmware, message ends up in the
+// firmware's log if the watchdog fires
+pub fn set(timeout: usize, code: u64, message: Option<&str>) -> ::Result<()> {
+	if timeout == 0 {
+		return disable();
+	}
+	match message {
+		Some(message) => {
+			let message = ::to_utf16(message);
+			unsafe { ::get_boot_services().set_watchdog_timer(timeout, code, message.len() * 2, message.as_ptr()) }.into_result()
+		},
+		None => unsafe { ::get_boot_services().set_watchdog_timer(timeout, code, 0, ptr::null()) }.into_result()
+	}
+}
+
+// gives us another timeout seconds, for long running work that wants to be reset if it hangs
+pub fn extend(timeout: usize) -> ::Result<()> {
+	set(timeout, 0x10000, None)
+}
+
+// puts the watchdog back the way the firmware had it when we started
+pub fn rearm() -> ::Result<()> {
+	set(DEFAULT_TIMEOUT, 0x10000, None)
+}